@@ -1,25 +1,43 @@
-from copy import deepcopy
+from contextlib import redirect_stdout
 from enum import StrEnum
+import io
 from re import match, sub
+import ipaddress
+import shutil
 import socket
+import tempfile
+from urllib.parse import urlparse
+import subprocess
 import yaml
 import sys
 import os
 
-from jinja2 import Template, meta, Environment, FileSystemLoader
+from jinja2 import Template, TemplateError, meta, Environment, FileSystemLoader
 from jinja2.nodes import Template as NodeTemplate
 import docker
+import networkx
 
+from dune.allocation import Allocator, Greedy
+from dune.config import is_remote
+from dune.errors import DuneError, collect, warn
+from dune.filters import FILTERS
 from dune.infrastructure import Infra
-from dune.topology import Topo, Pinned
+from dune.observer import Observer
+from dune.topology import Topo, Node, Pinned, LIMITS, IFACE_FLAGS, TUNNELS, bandwidth
 
 
 class ConfigSection(StrEnum):
+    """
+    Sections of a phynode config, in execution order.
+    Each section acts as a barrier: it must be completed on every phynode before the next one starts.
+    In particular, all the nodes' netns exist before any link is created, and Ready hooks run once every process is started.
+    """
     Pre = 'PreSetup'
     Nodes = 'Nodes'
     Links = 'Links'
     Post = 'PostSetup'
     Processes = 'Processes'
+    Ready = 'Ready'
     PreDown = 'PreDown'
     Down = 'Down'
 
@@ -31,6 +49,21 @@ class ConfigSection(StrEnum):
 #     return lo
 
 
+_transient = 'Device or resource busy|Resource temporarily unavailable'
+""" stderr patterns of the netlink errors worth retrying, i.e., EBUSY and EAGAIN. """
+
+_cgroups = '/sys/fs/cgroup/dune'
+""" Parent cgroup of the per-node cgroups. """
+
+_run = '/run/dune'
+""" Parent directory of the per-node pidfiles. """
+
+_captures = '/var/tmp/dune'
+""" Parent directory of the per-node packet captures. """
+
+_privileges = '[ $(( 0x$(awk "/^CapEff/ {print \\$2}" /proc/self/status) & 0x201000 )) -eq $(( 0x201000 )) ] || { echo "dune: CAP_NET_ADMIN and CAP_SYS_ADMIN are required, run as root" >&2; exit 1; }'
+""" Check that the effective capabilities include CAP_NET_ADMIN (bit 12) and CAP_SYS_ADMIN (bit 21). """
+
 _keywords = ['fun']
 _expr = '|'.join([f'^%{k} ' for k in _keywords])
 
@@ -51,27 +84,103 @@ def _expand_env(plugins: dict, env: dict):
 
     return {_apply(k): _expand_env(plugins, v) if isinstance(v, dict) else [_apply(entry) for entry in v] if isinstance(v, list) else _apply(v) for k, v in env.items()}
 
+def _link_id(head: str, head_iface: str, tail: str, tail_iface: str) -> str:
+    """ Return the canonical ID of the link between @p head:@p head_iface and @p tail:@p tail_iface.
+        Endpoints are sorted so that both directions of a link share the same ID, including self-loops.
+    """
+    (head, head_iface), (tail, tail_iface) = sorted([(head, head_iface), (tail, tail_iface)])
+    return f'{head}:{head_iface}-{tail}:{tail_iface}'
+
+def _address(nid: str, iface: str, addr: str) -> str:
+    """ Return the address @p addr of interface @p iface of node @p nid with its prefix length, e.g., 10.0.0.1/31.
+        An address without prefix is a host address, i.e., /32 or /128. DuneError is raised if @p addr is malformed.
+    """
+    try:
+        return str(ipaddress.ip_interface(addr))
+    except ValueError:
+        raise DuneError(f'Malformed address <{addr}> of <{nid}:{iface}>.')
+
 class Dune:
 
-    def __init__(self, base: str, topo: str):
+    def __init__(self, base: str, topo: str, profiles: list = None):
 
-        path = os.path.join(base, topo)
+        """ The config can also be read from stdin, i.e., '-', or an http(s) URL, the outputs are then written in @p base. """
+        path = topo if topo == '-' or is_remote(topo) else os.path.join(base, topo)
         self.base = base
-        self.name = topo
+        self.name = 'stdin' if topo == '-' else os.path.basename(urlparse(topo).path) if is_remote(topo) else topo
 
         self._plugins = {}
         self._load_plugins()
 
         # TODO: do not load twice if all config in a single file.
-        self.topo = Topo(path)
+        self.topo = Topo(path, profiles)
         self.infra = Infra(path)
         self._allocation = None
         self._configs = {}
         self._docker = None
+        self._nodes_ctx = None
+        self._teardown = []
+        """ Teardown instructions of pinned processes, as (order, node, pre_down, down), emitted once all nodes are added. """
+        self._run_dirs = set()
+        """ IDs of the nodes whose run directory, under /run/dune, is created, e.g., for pidfiles. It is removed on teardown. """
+        self._lo = {}
+        """ Expanded 'lo' addresses of each node, computed once as plugin calls may not be idempotent. """
+        self._undo = {}
+        """ Per phynode, commands reverting the resources created by a given (section, index) command, for rollback. """
+        self._vlans = {}
+        """ VLAN ID of each link between two phynodes. """
+        self.ensure = False
+        """ Whether the setup reconciles the phynodes, i.e., only creates what is missing, see build(). """
+        self._built = set()
+        """ IDs of the nodes set up by build(). """
+        self._observer = Observer()
+        """ Observer of the build milestones, see set_observer(). """
 
         if self.topo._total_cores > self.infra._total_cores:
-            print('Specified infrastructure has not enough cores to allocate each process.')
-            exit(1)
+            raise DuneError('Specified infrastructure has not enough cores to allocate each process.')
+
+        """ The management network holds the address of each phynode and node, besides the network and broadcast ones. """
+        if (management := self.topo.management) is not None:
+            if ipaddress.ip_network(management['subnet']).num_addresses < len(self.topo.nodes) + len(self.infra._cores) + 2:
+                raise DuneError(f'Management subnet {management["subnet"]} is too small for {len(self.topo.nodes)} nodes and {len(self.infra._cores)} phynodes.')
+
+    @staticmethod
+    def validate(base: str, topo: str, profiles: list = None) -> list:
+        """ Load, allocate and build the topology @p topo, as for a setup but without writing anything, to report every problem up front.
+            @param[in]  base        The directory of the config, as for the constructor.
+            @param[in]  topo        The config, as for the constructor.
+            @param[in]  profiles    The active profiles, if any.
+            @return     The diagnostics, i.e., [{severity, message}]. The warnings reported on the way, e.g., overlapping subnets, are
+                        warnings and the problem aborting the setup, if any, is an error, whatever its exception.
+        """
+
+        error = None
+        with collect() as warnings, redirect_stdout(io.StringIO()):
+            try:
+                Dune(base, topo, profiles).build()
+            except DuneError as e:
+                error = str(e)
+            except Exception as e:
+                error = f'{type(e).__name__}: {e}'
+
+        diagnostics = [dict(severity='warning', message=warning) for warning in warnings]
+        if error is not None: diagnostics.append(dict(severity='error', message=error))
+        return diagnostics
+
+    def __str__(self):
+        """ Concise description of the allocation, one line per node with its cores, then one line per interface, e.g.,
+            r0 on srv1, cores [[1]]
+              eth0 [10.0.0.1/31] mtu=1500 -> r1:eth0
+        """
+
+        lines = []
+        for nid, (phynode, cores) in self.allocate().items():
+            lines.append(f'{nid} on {phynode}, cores {cores}')
+            ifaces = {local: (peer, peer_iface, data) for (_, peer, (local, peer_iface), data) in self.topo.edges(nid, data=True, keys=True)}
+            for iface, (peer, peer_iface, data) in sorted(ifaces.items()):
+                mtu = f' mtu={data["mtu"]}' if 'mtu' in data else ''
+                lines.append(f'  {iface} [{", ".join(self.topo.nodes[nid]["cfg"]._addresses.get(iface) or [])}]{mtu} -> {peer}:{peer_iface}')
+        return '\n'.join(lines)
 
     def _load_plugins(self):
         plugins_dir = os.path.join(self.base, 'plugins')
@@ -84,37 +193,150 @@ class Dune:
             self._plugins[name] = module
 
     def allocate(self) -> dict:
-        # TODO: clever way with buckets and CP, fill gaps if any
+        """ Allocate the nodes with the default, greedy, allocator, if not already allocated. """
 
         if self._allocation is not None: return self._allocation
+        return self.allocate_with_allocator(Greedy())
+
+    def set_observer(self, observer: Observer):
+        """ Notify @p observer, e.g., a user-defined subclass of Observer, of the milestones of the next builds. """
+        self._observer = observer
+
+    def allocate_with_allocator(self, allocator: Allocator) -> dict:
+        """ Allocate the nodes with @p allocator, replacing any previous allocation.
+            @param[in]  allocator   The allocator, e.g., a user-defined subclass of Allocator.
+            @return     The allocation, i.e., {nid: (phynode, [[cores of process 0], ...])}.
+            @pre        The topology is not built yet.
+        """
+
+        self._allocation = allocator.allocate(self.topo, self.infra)
+        self._nodes_ctx = None
+        if self._allocation is None:
+            raise DuneError(f'Allocator <{type(allocator).__name__}> failed.')
 
-        self._allocation = {}
-
-        available = deepcopy(self.infra._cores)
-
-        for node, count in sorted(
-                {nid: node['cfg']._get_n_cores() for nid, node in self.topo.nodes(data=True)}.items(),
-                key=lambda item: item[1],
-                reverse=True):
-
-            alloc = []
-            phynode0 = None
-            for process in self.topo.nodes[node]['cfg']._get_cores():
-                b = False
-                for phynode, cores in available.items():
-                    if b: break
-                    for numa in cores:
-                        # TODO: use list:
-                        if type(numa) != list: continue
-                        if len(numa) >= count:
-                            alloc.append([numa.pop() for _ in process])
-                            b = True
-                            phynode0 = phynode
-                            break
-            self._allocation[node] = (phynode0, alloc)
+        """ Every node must be mapped to a phynode of the infrastructure. """
+        unallocated = [nid for nid in self.topo.nodes if nid not in self._allocation or self._allocation[nid][0] not in self.infra._cores]
+        if len(unallocated) > 0:
+            raise DuneError(f'Nodes {unallocated} could not be mapped to a phynode of the infrastructure {list(self.infra._cores)}.')
+
+        for nid in self._allocation: self._check_cores(nid)
 
         return self._allocation
 
+    def cores_usage(self) -> dict:
+        """ Summarize the usage of the cores of each phynode, allocating the nodes if not already done.
+            @return     {phynode: {total, allocated, reserved, free}}, reserved cores being the SMT siblings withheld from other nodes.
+        """
+
+        return {phynode: dict(total=len(total), allocated=len(allocated), reserved=len(reserved), free=len(total - allocated - reserved))
+                for phynode, (total, allocated, reserved) in self._cores_sets().items()}
+
+    def free_cores(self) -> dict:
+        """ Return the cores of each phynode left once the nodes are allocated, allocating them if not already done.
+            The infrastructure is left untouched, its cores being the configured ones.
+            @return     {phynode: [free cores]}, the SMT siblings withheld from other nodes being excluded.
+        """
+        return {phynode: sorted(total - allocated - reserved) for phynode, (total, allocated, reserved) in self._cores_sets().items()}
+
+    def numa_nodes(self, nid: str) -> list:
+        """ Return the NUMA node ids, as labeled in the infrastructure, of the cores allocated to node @p nid, allocating it if needed. """
+        phynode, alloc = self.allocate()[nid]
+        return sorted(set(self.infra.numa_of(phynode, core) for cores in alloc for core in cores))
+
+    def _cores_sets(self) -> dict:
+        """ Return the total, allocated and reserved cores of each phynode, i.e., {phynode: (total, allocated, reserved)}. """
+
+        sets = {}
+        allocation = self.allocate()
+        for phynode, blocks in self.infra._cores.items():
+            total = set(c for block in blocks for c in (block if type(block) == list else [block]))
+            allocated = set(c for pid, alloc in allocation.values() if pid == phynode for cores in alloc for c in cores)
+            reserved = set()
+            if self.infra.smt != 'split':
+                reserved = set(s for c in allocated for s in self.infra.siblings.get(phynode, {}).get(c, [])) & total - allocated
+            sets[phynode] = (total, allocated, reserved)
+
+        return sets
+
+    def node_count(self) -> int:
+        """ Return the number of nodes of the topology. """
+        return len(self.topo.nodes)
+
+    def phynode_count(self) -> int:
+        """ Return the number of phynodes of the infrastructure. """
+        return len(self.infra._cores)
+
+    def stats(self, verbose: bool = True) -> dict:
+        """ Summarize the topology and the usage of the cores of each phynode, allocating the nodes if not already done.
+            @param[in]  verbose     Whether to also print the summary.
+            @return     {nodes, phynodes, links, addresses, processes, cores, usage}, i.e., the number of nodes, phynodes, links,
+                        addresses including loopback ones, pinned processes and cores requested, and the cores usage of each phynode.
+        """
+
+        nodes = [node for _, node in self.topo.nodes(data='cfg')]
+        stats = dict(
+            nodes=self.node_count(),
+            phynodes=self.phynode_count(),
+            links=len(self.links()),
+            addresses=sum(len(addrs or []) for nid in self.topo.nodes for addrs in {**self.topo.nodes[nid]['cfg']._addresses, 'lo': self._lo_addresses(nid)}.values()),
+            processes=sum(len(node.pinned or []) for node in nodes),
+            cores=sum(node._get_n_cores() for node in nodes),
+            usage=self.cores_usage(),
+        )
+
+        if verbose:
+            print(f'{stats["nodes"]} nodes on {stats["phynodes"]} phynodes, {stats["links"]} links, {stats["addresses"]} addresses, '
+                  f'{stats["processes"]} pinned processes requesting {stats["cores"]} cores.')
+            for phynode, usage in stats['usage'].items():
+                print(f'{phynode}: {usage["allocated"]}/{usage["total"]} cores allocated, {usage["reserved"]} reserved, {usage["free"]} free.')
+
+        return stats
+
+    def node(self, nid: str) -> Node:
+        """ Return the configuration of node @p nid, or None if it is not defined. """
+        return self.topo.nodes[nid]['cfg'] if nid in self.topo.nodes else None
+
+    def phynode_for(self, nid: str) -> str:
+        """ Return the phynode on which node @p nid is allocated, or None if it is not defined. """
+        return self._node_to_phynode(nid) if nid in self.topo.nodes else None
+
+    def nodes_of_phynode(self, phynode: str) -> list:
+        """ Return the IDs of the nodes allocated on @p phynode. """
+        return [nid for nid in self.topo.nodes if self._node_to_phynode(nid) == phynode]
+
+    def nodes_on(self, phynode: str):
+        """ Iterate over the (ID, configuration) pairs of the nodes allocated on @p phynode. """
+        for nid in self.nodes_of_phynode(phynode):
+            yield nid, self.topo.nodes[nid]['cfg']
+
+    def interface(self, nid: str, iface: str) -> dict:
+        """ Return the attributes of interface @p iface of node @p nid, or None if it is not defined.
+            @return     The link attributes with the peer node and interface, and the interface addresses.
+        """
+        if (node := self.node(nid)) is None: return None
+        addrs = node._addresses.get(iface, [])
+
+        for (_, peer, (local, peer_iface), data) in self.topo.edges(nid, data=True, keys=True):
+            if local == iface:
+                return dict(peer=peer, peer_iface=peer_iface, addrs=addrs, **data)
+
+        if node.physical is not None and iface in node.physical:
+            return dict(peer=None, physical=node.physical[iface], addrs=addrs)
+
+        return None
+
+    def _check_cores(self, nid: str):
+
+        """ Compare the core ids requested by the processes of node @p nid with the allocated cores.
+            @post       DuneError is raised if a process did not get all its cores.
+        """
+
+        _, alloc = self._allocation[nid]
+        for idx, cores in enumerate(self.topo.nodes[nid]['cfg']._get_cores()):
+            allocated = len(alloc[idx]) if idx < len(alloc) else 0
+            if allocated != len(cores):
+                raise DuneError(f'Process {idx} of node <{nid}> requests {len(cores)} cores but {allocated} were allocated.')
+
     def _node_to_phynode(self, nid: str) -> str:
 
         """ Return the corresponding phynode for a given node id.
@@ -146,36 +368,142 @@ class Dune:
             except KeyError:
                 self._configs[pid] = {section: [cmd]}
 
-    def _node_exec(self, nid: str, section: ConfigSection, cmd: str, environ: dict = None):
+    def _on_rollback(self, pid: str, section: ConfigSection, undo: str):
+        """ Register @p undo to revert the last command of @p section on phynode @p pid if the setup fails.
+            @param[in]  pid     The target phynode.
+            @param[in]  section The section of the command creating the resource.
+            @param[in]  undo    The command reverting it.
+        """
+        if self.ensure: return
+        self._undo.setdefault(pid, {})[(section, len(self._configs[pid][section]) - 1)] = undo
+
+    def _verb(self) -> str:
+        """ Return the ip verb adding addresses and routes, i.e., 'replace' in ensure mode so that existing ones are kept. """
+        return 'replace' if self.ensure else 'add'
+
+    def _node_exec(self, nid: str, section: ConfigSection, cmd: str, environ: dict = None, cgroup: str = None):
         """ Execute @p cmd in the netns corresponding to the node @p node_id
             @param      nid The ID of the node on which @p cmd has to be executed.
             @param      cmd     The command to execute on node @p node_id.
+            @param      cgroup  The cgroup, if any, to join before entering the netns.
             @post                   The command has been successfully added to the XML output file.
             @todo                   Check the post-condition.
         """
         phynode = self._node_to_phynode(nid)
         environ = '' if environ is None else ' '.join([f'{k}={v}' for k,v in environ.items()]) + ' '
-        self._phynode_exec(phynode, section, f'{environ}ip netns exec {nid} bash -c "{cmd}"')
+
+        """ 'ip netns exec' remounts /sys, the cgroup must thus be joined from the phynode. """
+        cgroup = '' if cgroup is None else f'sh -c \'echo $$ > {cgroup}/cgroup.procs && exec "$@"\' {nid} '
+        self._phynode_exec(phynode, section, f'{environ}{cgroup}ip netns exec {nid} bash -c "{cmd}"')
+
+    def exec_in_node(self, nid: str, cmd: str) -> subprocess.CompletedProcess:
+        """ Run @p cmd in the netns of node @p nid on the local phynode, e.g., to verify a running topology.
+            @param[in]  nid     The ID of the node in which @p cmd has to be executed.
+            @param[in]  cmd     The shell command to execute.
+            @return     The completed process with its captured stdout and stderr.
+        """
+        if nid not in self.topo.nodes:
+            raise DuneError(f'Node <{nid}> not defined.')
+
+        return subprocess.run(['ip', 'netns', 'exec', nid, 'bash', '-c', cmd], capture_output=True, text=True)
 
     def _node_pinned(self, nid: str, pinned: Pinned, idx: int):
         _, cores = self._allocation[nid]
-        cores = cores[idx]
+        cores = cores[idx] if idx < len(cores) else []
         env = Environment()
         ast = env.parse(pinned.cmd)
         cores = {k: cores[idx] for idx,(k, v) in enumerate(pinned._get_cores().items())}
-        environ = None if pinned.environ is None else {k: Template(v).render(**cores) for k, v in pinned.environ.items()}
 
+        """ The command, its environment and teardown are rendered with the node ID and the allocated core of each core_N. """
         renv = {'node': nid, **cores}
+        environ = None if pinned.environ is None else {k: Template(v).render(renv) for k, v in pinned.environ.items()}
         cmd = Template(pinned.cmd).render(renv)
 
-        self._node_exec(nid, ConfigSection.Processes, f'taskset -c {cores["core_0"]} {cmd}', environ)
+        """ Privileges are dropped last, the launcher may require them, and setpriv execs the process to keep its PID. """
+        if pinned.user is not None:
+            group = pinned.group if pinned.group is not None else f'\\$(id -g {pinned.user})'
+            cmd = f'setpriv --reuid={pinned.user} --regid={group} --init-groups {cmd}'
+        if pinned.launcher is not None: cmd = f'{Template(pinned.launcher).render(renv)} {cmd}'
+        if 'core_0' in cores: cmd = f'taskset -c {cores["core_0"]} {cmd}'
+
+        """
+        A graceful shutdown signals the process, its PID is thus tracked in a pidfile, as in ensure mode to not launch it twice.
+        The pidfile is the one written by the process, if it daemonizes, else the process is launched in the background, with or
+        without a trailing '&', and its PID recorded.
+        """
+        pidfile = None
+        if pinned.pidfile is not None:
+            pidfile = Template(pinned.pidfile).render(renv)
+        elif type(pinned.down) == dict or self.ensure:
+            pidfile = f'{_run}/{nid}/{idx}.pid'
+            self._add_run_dir(nid)
+            if (cmd := cmd.rstrip()).endswith('&') and not cmd.endswith('&&'): cmd = cmd[:-1].rstrip()
+            cmd = f'{cmd} & echo \\$! > {pidfile}'
+        if pidfile is not None and self.ensure: cmd = f'kill -0 \\$(cat {pidfile} 2> /dev/null) 2> /dev/null || {{ {cmd}; }}'
+
+        cgroup = None if self.topo.nodes[nid]['cfg'].limits is None else f'{_cgroups}/{nid}'
+        self._node_exec(nid, ConfigSection.Processes, cmd, environ, cgroup)
+        self._observer.on_process(nid, idx, cmd)
+
+        """ Defer teardown instructions, they are sorted by order across all nodes. """
+        pre_down = [Template(instruction).render(renv) for instruction in pinned.pre_down or []]
+        if type(pinned.down) == dict:
+            down = self._graceful_down(pidfile, pinned.down)
+        else:
+            down = None if pinned.down is None else f'ip netns exec {nid} bash -c "{Template(pinned.down).render(renv)}"'
+        self._teardown.append((pinned.order, nid, pre_down, down))
+
+    def _graceful_down(self, pidfile: str, down: dict) -> str:
+        """ Build the shutdown of the process tracked in @p pidfile.
+            @param[in]  pidfile The pidfile of the process.
+            @param[in]  down    The signal to send, the timeout to wait for the process to exit and the signal, if any, to send then.
+            @return     The phynode command. A process that is already gone is ignored.
+        """
+        signal, then = Pinned._check_signal(down['signal']), down.get('then')
+        wait = f'timeout {down["timeout"]} tail --pid=$pid -f /dev/null'
+        if then is not None: wait = f'{wait} || kill -s {Pinned._check_signal(then)} $pid'
+        return f'if pid=$(cat {pidfile} 2>/dev/null) && kill -s {signal} $pid 2>/dev/null; then {wait}; fi; rm -f {pidfile}'
+
+    def _add_teardown(self, nids: set):
+        """ Emit the teardown instructions of pinned processes of the nodes @p nids, lower orders first.
+            Processes of the same order are torn down in reverse startup order, i.e., nodes before their dependencies, the processes of a
+            node in declaration order.
+        """
+
+        rank = {nid: idx for idx, nid in enumerate(self.topo.startup_order())}
+        for _, nid, pre_down, down in sorted(self._teardown, key=lambda entry: (entry[0], -rank[entry[1]])):
+            for instruction in pre_down:
+                self._node_exec(nid, ConfigSection.PreDown, instruction)
+            if down is not None:
+                self._phynode_exec(self._node_to_phynode(nid), ConfigSection.Down, down)
+
+    def _add_run_dir(self, nid: str):
+        """ Create the run directory of node @p nid, if not already done, before its processes are launched. """
+        if nid in self._run_dirs: return
+        self._run_dirs.add(nid)
+        self._phynode_exec(self._node_to_phynode(nid), ConfigSection.Processes, f'mkdir -p {_run}/{nid}')
+
+    def _add_cleanup(self, nids: set):
+        """ Remove the cgroup and the run directory of the nodes @p nids on teardown.
+            @pre        Every Down command of the nodes is emitted, e.g., the ones reading pidfiles from their run directory.
+        """
 
-        """ Add down instruction. """
-        if pinned.down is not None:
-            self._node_exec(nid, ConfigSection.Down, Template(pinned.down).render(renv))
+        """ Remove the cgroup and the run directory of the nodes once their processes are down. """
+        for nid, node in self.topo.nodes(data='cfg'):
+            if nid not in nids: continue
+            if node.limits is not None:
+                self._phynode_exec(self.phynode_for(nid), ConfigSection.Down, f'rmdir {_cgroups}/{nid}')
+            if nid in self._run_dirs:
+                self._phynode_exec(self.phynode_for(nid), ConfigSection.Down, f'rm -rf {_run}/{nid}')
 
-        # TODO: add PreDown
 
+    def _retry(self, cmd: str) -> str:
+        """ Wrap @p cmd in a bounded retry loop with exponential backoff.
+            @param[in]  cmd     The command to retry.
+            @return     The wrapped command. Only transient errors are retried, others fail immediately.
+        """
+        if self.infra.retries == 1: return cmd
+        return f'(n=1; until err=$({cmd} 2>&1); do echo "$err" >&2; [ $n -lt {self.infra.retries} ] && echo "$err" | grep -qE "{_transient}" || exit 1; sleep $((1 << (n - 1))); n=$((n + 1)); done)'
 
     def _ip(self, section: ConfigSection, cmd: str, nid: str = None):
         """ Run an ip-based command on a phynode.
@@ -186,7 +514,7 @@ class Dune:
         """
         phynode = self._node_to_phynode(nid)
         nid = f'-n {nid} ' if nid is not None else ''
-        self._phynode_exec(phynode, section, f'ip {nid}{cmd}')
+        self._phynode_exec(phynode, section, self._retry(f'ip {nid}{cmd}'))
 
     def _sysctl(self, nid: str, section: ConfigSection, sysctl: str, value: str):
         # TODO: check if sysctl is valid
@@ -198,6 +526,88 @@ class Dune:
         # TODO: handle errors
         return list(self.topo.nodes).index(nid)
 
+    def _lo_addresses(self, nid: str) -> list:
+        """ Return the 'lo' addresses of node @p nid, expanded from templates and plugin calls once and cached. """
+        if nid not in self._lo:
+            if not (lo := self.topo.nodes[nid]['cfg']._addresses.get('lo')): self._lo[nid] = []
+            else:
+                lo = yaml.safe_load(Template(str(lo)).render(dict(node=nid)))
+                self._lo[nid] = _expand_env(self._plugins, dict(addresses=dict(lo=lo)))['addresses']['lo']
+        return self._lo[nid]
+
+    def _management_address(self, nid: str) -> str:
+        """ Return the address of node @p nid on the management network, the first ones being the phynodes'. """
+        net = ipaddress.ip_network(self.topo.management['subnet'])
+        return f'{net.network_address + len(self.infra._cores) + self._get_node_id(nid) + 1}/{net.prefixlen}'
+
+    def _management_gateway(self, phynode: str) -> str:
+        """ Return the address of @p phynode on the management network, i.e., of its bridge, in the order of the infrastructure. """
+        net = ipaddress.ip_network(self.topo.management['subnet'])
+        return f'{net.network_address + list(self.infra._cores).index(phynode) + 1}/{net.prefixlen}'
+
+    def _nodes_context(self) -> dict:
+        """ Return a lightweight view of every node, exposed as 'nodes' to templates, e.g., to build a full mesh. """
+        if self._nodes_ctx is None:
+            self._nodes_ctx = {nid: dict(
+                phynode=self._node_to_phynode(nid),
+                addrs={**node._addresses, 'lo': self._lo_addresses(nid)},
+                management=None if self.topo.management is None else self._management_address(nid),
+                numa=self.numa_nodes(nid),
+                label=node.label,
+                tags=node.tags,
+            ) for nid, node in self.topo.nodes(data='cfg')}
+        return self._nodes_ctx
+
+    def _node_ips(self, nid: str) -> list:
+        """ Return the IP addresses, without prefix, assigned to the interfaces of node @p nid. """
+        ips = []
+        for addrs in {**self.topo.nodes[nid]['cfg']._addresses, 'lo': self._lo_addresses(nid)}.values():
+            for addr in addrs or []:
+                try:
+                    ips.append(str(ipaddress.ip_interface(addr).ip))
+                except ValueError:
+                    pass
+        return ips
+
+    def _check_addresses(self):
+        """ Cross-check the addresses of every interface of every node.
+            @post       DuneError is raised if an address is assigned to multiple interfaces, except to the 'lo' of several nodes,
+                        i.e., anycast addresses.
+            @post       Overlapping subnets on interfaces that are not on the same link are reported.
+        """
+
+        ifaces = {}
+        for nid, node in self.topo.nodes(data='cfg'):
+            for iface, addrs in {**node._addresses, 'lo': self._lo_addresses(nid)}.items():
+                for addr in addrs or []:
+                    try:
+                        ifaces.setdefault((nid, iface), []).append(ipaddress.ip_interface(addr))
+                    except ValueError:
+                        pass
+
+        links = set((head, local, tail, peer) for head, tail, (local, peer) in self.topo.edges(keys=True))
+
+        duplicates = []
+        endpoints = list(ifaces.items())
+        for idx, ((nid, iface), addrs) in enumerate(endpoints):
+            for (peer, peer_iface), peer_addrs in endpoints[idx + 1:]:
+                for addr in addrs:
+                    for peer_addr in peer_addrs:
+                        if addr.ip == peer_addr.ip:
+                            if iface == peer_iface == 'lo' and nid != peer: continue
+                            duplicates.append(f'Address {addr.ip} assigned to both <{nid}:{iface}> and <{peer}:{peer_iface}>.')
+                        elif addr.network.overlaps(peer_addr.network) and (nid, iface, peer, peer_iface) not in links:
+                            warn(f'Subnets {addr.network} of <{nid}:{iface}> and {peer_addr.network} of <{peer}:{peer_iface}> overlap.')
+
+        if len(duplicates) > 0: raise DuneError('\n'.join(duplicates))
+
+    def _hosts(self) -> str:
+        """ Return the hosts file mapping the loopback addresses of every node to its name. """
+        hosts = ['127.0.0.1 localhost', '::1 localhost']
+        for nid, node in self._nodes_context().items():
+            hosts.extend(f'{ipaddress.ip_interface(addr).ip} {nid}' for addr in node['addrs']['lo'])
+        return '\n'.join(hosts) + '\n'
+
     def _add_node(self, nid: str):
 
         """Add a node with @p node_id to the topology.
@@ -214,20 +624,35 @@ class Dune:
         node = self.topo.nodes[nid]['cfg']
         node_idx = list(self.topo.nodes).index(nid)
 
+        """ Prerequisites, if any, run on the phynode before the netns and interfaces exist. """
+        for cmd in node.pre_up:
+            self._phynode_exec(phynode, section, Template(cmd).render(dict(node=nid, **node.env)))
+
         """ Add a netns with ID @p nid on the corresponding phynode """
-        self._phynode_exec(phynode, section, f'ip netns add {nid}')
+        if node.external_ns:
+            self._phynode_exec(phynode, section, f'[ -e /var/run/netns/{nid} ] || {{ echo "dune: external netns {nid} not found" >&2; false; }}')
+        else:
+            self._phynode_exec(phynode, section, f'[ -e /var/run/netns/{nid} ] || ip netns add {nid}' if self.ensure else f'ip netns add {nid}')
+            self._on_rollback(phynode, section, f'ip netns pids {nid} | xargs -r kill; ip netns del {nid}')
 
         """ Set 'lo' addresses if specified or required. """
-        if (lo:=node._addresses.get('lo')):
-            lo = yaml.safe_load(Template(str(lo)).render(dict(node=nid)))
-            lo = _expand_env(self._plugins, dict(addresses=dict(lo=lo)))
-            for address in lo['addresses']['lo']:
-                self._ip(section, f'a add {address} dev lo', nid)
+        for address in self._lo_addresses(nid):
+            self._ip(section, f'a {self._verb()} {_address(nid, "lo", address)} dev lo', nid)
 
         # TODO: Check if auto-generation is requested with prefixes
 
         self._ip(section, 'l set dev lo up', nid)
 
+        """ Management interface, if any, plugged into the management bridge of the phynode. """
+        if (management := self.topo.management) is not None:
+            iface, peer = management['iface'], f'dm{self._get_node_id(nid)}'
+            veth = self._retry(f'ip l add dev {iface} netns {nid} type veth peer name {peer}')
+            if self.ensure: veth = f'ip -n {nid} l show dev {iface} > /dev/null 2>&1 || {veth}'
+            self._phynode_exec(phynode, section, veth)
+            self._phynode_exec(phynode, section, f'ip l set dev {peer} master {management["bridge"]} up')
+            self._ip(section, f'a {self._verb()} {self._management_address(nid)} dev {iface}', nid)
+            self._ip(section, f'l set dev {iface} up', nid)
+
         """ Apply execs if any. """
         if node.execs is not None:
             for cmd in node.execs:
@@ -238,6 +663,15 @@ class Dune:
             for sysctl, value in node.sysctls.items():
                 self._sysctl(nid, section, sysctl, value)
 
+        """ Create the cgroup of the node, if limits are specified. """
+        if node.limits is not None:
+            cgroup = f'{_cgroups}/{nid}'
+            self._phynode_exec(phynode, section, f'mkdir -p {cgroup}')
+            self._on_rollback(phynode, section, f'echo 1 > {cgroup}/cgroup.kill; while [ -s {cgroup}/cgroup.procs ]; do sleep 0.1; done; rmdir {cgroup}')
+            self._phynode_exec(phynode, section, f'echo "+cpu +memory" > {_cgroups}/cgroup.subtree_control')
+            for limit, value in node.limits.items():
+                self._phynode_exec(phynode, section, f'echo "{value}" > {cgroup}/{LIMITS[limit]}')
+
         """ Apply pinned processes, if any. """
         if node.pinned is not None:
             for idx, process in enumerate(node.pinned):
@@ -254,25 +688,40 @@ class Dune:
 
             for template, data in node.templates.items():
                 ifaces = {iface: dict(peer=peer, **data) for (_, peer, (iface, _), data) in self.topo.edges(nid, data=True, keys=True)}
+                if node.physical is not None:
+                    ifaces.update({iface: dict(peer=None, physical=host_iface) for iface, host_iface in node.physical.items()})
+                for iface, attrs in ifaces.items():
+                    attrs['alias'] = self.topo.aliases.get((nid, iface), iface)
+                    attrs['addrs'] = list(node._addresses.get(iface) or [])
+
+                """ Interfaces are sorted by name, so that rendered files do not depend on the declaration order. """
+                ifaces = dict(sorted(ifaces.items()))
                 renv = {
                     'rid': node.env['rid'] if 'rid' in node.env else socket.inet_ntoa(socket.inet_aton(str(node_idx+1))),
                     'ifaces': ifaces,
                     'node': nid,
+                    'nodes': self._nodes_context(),
                 }
                 renv.update(nenv)
 
                 """ Template rendering with final expanded environment. """
-                data['content'] = self._generate_template(template, renv)
+                data['content'] = self._generate_template(template, renv, {**self.topo.template_syntax, **data.get('syntax', {})})
                 data['dst'] = Template(data['dst']).render({'node': nid})
 
+        """ Binds are copied when dumped, their sources must exist. Their destinations are rendered like those of templates. """
+        for src, dst in node.binds.items():
+            if not os.path.exists(os.path.join(self.base, src)):
+                raise DuneError(f'Bind source <{src}> of node <{nid}> not found.')
+            node.binds[src] = Template(dst).render({'node': nid})
+
+        self._observer.on_node(nid, phynode)
 
     def _get_builder(self, builder: str):
 
         builder_cfg = self.infra.builders.get(builder)
 
         if builder_cfg is None:
-            print(f'Builder <{builder}> not defined.')
-            exit(1)
+            raise DuneError(f'Builder <{builder}> not defined.')
 
         # TODO: sanity check on builder_cfg
 
@@ -287,8 +736,9 @@ class Dune:
             labels={'dune.builder': '1'}
         )
 
-    def _generate_template(self, template: str, data: dict) -> str:
-        env = Environment(loader=FileSystemLoader(os.path.join(self.base, 'templates'), followlinks=True))
+    def _generate_template(self, template: str, data: dict, syntax: dict = None) -> str:
+        env = Environment(loader=FileSystemLoader(os.path.join(self.base, 'templates'), followlinks=True), **(syntax or {}))
+        env.filters.update(FILTERS)
         return env.get_template(template).render(data)
 
         # with open(os.path.join(self.base, template), 'r') as fd:
@@ -319,41 +769,232 @@ class Dune:
         head_phynode = self._node_to_phynode(head)
         tail_phynode = self._node_to_phynode(tail)
         head_iface, tail_iface = ifaces
+        tail_data = self.topo.edges[tail, head, (tail_iface, head_iface)]
+
+        if (kind := data.get('kind', 'veth')) != 'veth':
 
-        if head_phynode == tail_phynode:
+            """ Both ends are tunnel interfaces over the underlay, e.g., to emulate an overlay, whatever their phynodes. """
+            for nid, iface, local, remote in [(head, head_iface, data['local'], tail_data['local']), (tail, tail_iface, tail_data['local'], data['local'])]:
+                if local not in self._node_ips(nid):
+                    raise DuneError(f'Underlay address {local} of tunnel <{nid}:{iface}> is not assigned to node <{nid}>.')
+                tunnel = self._retry(f'ip -n {nid} l add {iface} type {TUNNELS[kind, ipaddress.ip_address(local).version]} local {local} remote {remote}')
+                if self.ensure: tunnel = f'ip -n {nid} l show dev {iface} > /dev/null 2>&1 || {tunnel}'
+                self._phynode_exec(self._node_to_phynode(nid), section, tunnel)
+
+        elif head_phynode == tail_phynode:
 
             """ Both ends of the link lie on the same phynode, link is a veth pair. """
-            self._phynode_exec(head_phynode, section, f'ip l add dev {head_iface} netns {head} type veth peer name {tail_iface} netns {tail}')
+            veth = self._retry(f'ip l add dev {head_iface} netns {head} type veth peer name {tail_iface} netns {tail}')
+            if self.ensure:
+                """ A leftover end without its peer is deleted so that the pair is recreated with the configured names. """
+                stale = '; '.join(f'ip -n {nid} l del dev {iface} 2> /dev/null || true' for nid, iface in [(head, head_iface), (tail, tail_iface)])
+                veth = f'{{ ip -n {head} l show dev {head_iface} && ip -n {tail} l show dev {tail_iface}; }} > /dev/null 2>&1 || {{ {stale}; {veth}; }}'
+            self._phynode_exec(head_phynode, section, veth)
 
         else:
 
-            """ Both nodes are on separate phynodes, we create vlan-defined links """
-            # TODO: create VLAN and interfaces
-            head_idx = self._get_node_idx(head)
-            tail_idx = self._get_node_idx(tail)
-            vlan_id = f'0x{head_idx :02x}{tail_idx :02x}'
-            print(vlan_id)
-
-        """ Set link properties """
-        delay = data['latency'] if 'latency' in data else '0ms'
-        bw = data['bw'] if 'bw' in data else '1gbit'
-        self._node_exec(head, section, f'tc qdisc add dev {head_iface} root netem delay {delay} rate {bw}')
-        self._node_exec(tail, section, f'tc qdisc add dev {tail_iface} root netem delay {delay} rate {bw}')
-        if (mtu := data.get('mtu')) is not None:
-            self._ip(section, f'l set dev {head_iface} mtu {mtu}', head)
-            self._ip(section, f'l set dev {tail_iface} mtu {mtu}', tail)
+            """ Both nodes are on separate phynodes, the link is a VLAN over the uplink of each phynode. """
+            if (link_id := _link_id(head, head_iface, tail, tail_iface)) not in self._vlans:
+                vid = len(self._vlans) + 2
+                """ The VLAN of the management network, if any, is skipped. """
+                if self.topo.management is not None and vid >= self.topo.management['vlan']: vid += 1
+                self._vlans[link_id] = vid
+            if (vid := self._vlans[link_id]) > 4094: raise DuneError('Too many links between phynodes, VLAN IDs are exhausted.')
+            for nid, iface, phynode in [(head, head_iface, head_phynode), (tail, tail_iface, tail_phynode)]:
+                if (uplink := self.infra.uplinks.get(phynode)) is None:
+                    raise DuneError(f'Phynode <{phynode}> has no \'uplink\' for link <{head}:{head_iface}-{tail}:{tail_iface}>.')
+                vlan = self._retry(f'ip l add link {uplink} name {iface} netns {nid} type vlan id {vid}')
+                if self.ensure: vlan = f'ip -n {nid} l show dev {iface} > /dev/null 2>&1 || {vlan}'
+                self._phynode_exec(phynode, section, vlan)
+
+        """ Set link properties, each end has its own attributes as they may be overridden per endpoint. """
+        ends = [(head, head_iface, data), (tail, tail_iface, tail_data)]
+        for nid, iface, attrs in ends:
+            if (alias := self.topo.aliases.get((nid, iface))) is not None:
+                self._ip(section, f'l set dev {iface} alias {alias}', nid)
+
+            delay = attrs['latency'] if 'latency' in attrs else '0ms'
+
+            """ A specified bandwidth is shaped by tbf, with its burst and limit, under netem which applies the impairments. """
+            if 'bw' in attrs:
+                bw = bandwidth(attrs['bw'])
+                self._node_exec(nid, section, f'tc qdisc replace dev {iface} root handle 1: netem delay {delay}')
+                self._node_exec(nid, section, f'tc qdisc replace dev {iface} parent 1:1 handle 10: tbf rate {bw["rate"]} burst {bw["burst"]} limit {bw["limit"]}')
+                parent = '10:1 handle 20:'
+            else:
+                self._node_exec(nid, section, f'tc qdisc replace dev {iface} root handle 1: netem delay {delay} rate 1gbit')
+                parent = '1:1 handle 10:'
+
+            """ Queueing discipline, if any, e.g., 'fq_codel limit 1000', as the innermost qdisc. """
+            if (qdisc := attrs.get('qdisc')) is not None:
+                self._node_exec(nid, section, f'tc qdisc replace dev {iface} parent {parent} {qdisc}')
+            self._node_exec(nid, ConfigSection.Down, f'tc qdisc del dev {iface} root')
+
+            """ Interface flags, before addresses are assigned. """
+            for flag, value in (attrs.get('flags') or {}).items():
+                self._sysctl(nid, section, IFACE_FLAGS[flag].format(iface=iface), int(value))
+
+            if (txqueuelen := attrs.get('txqueuelen')) is not None:
+                self._ip(section, f'l set dev {iface} txqueuelen {txqueuelen}', nid)
+
+            """ Promiscuous and all-multicast modes, e.g., for monitoring nodes. """
+            for mode in ['promisc', 'allmulticast']:
+                if mode in attrs:
+                    self._ip(section, f'l set dev {iface} {mode} {"on" if attrs[mode] else "off"}', nid)
+
+            """ Offloads, e.g., {tso: off, gro: off}, veth defaults skewing throughput measurements. """
+            if (offloads := attrs.get('offloads')):
+                features = ' '.join(f'{feature} {"on" if value else "off"}' for feature, value in offloads.items())
+                self._phynode_exec(self._node_to_phynode(nid), section, f'ip netns exec {nid} ethtool -K {iface} {features} || {{ echo "{nid}:{iface}: failed to set offloads {features}" >&2; false; }}')
+
+        """ MTU is symmetric unless the link is explicitly 'asymmetric_mtu', e.g., to test PMTUD. """
+        for nid, iface, attrs in ends:
+            if (mtu := attrs.get('mtu')) is not None:
+                self._ip(section, f'l set dev {iface} mtu {mtu}', nid)
 
         if (addrs := self.topo.nodes[head]['cfg']._addresses.get(head_iface)):
             for addr in addrs:
-                self._ip(section, f'a add {addr} dev {head_iface}', head)
+                self._ip(section, f'a {self._verb()} {_address(head, head_iface, addr)} dev {head_iface}', head)
 
         if (addrs := self.topo.nodes[tail]['cfg']._addresses.get(tail_iface)):
             for addr in addrs:
-                self._ip(section, f'a add {addr} dev {tail_iface}', tail)
+                self._ip(section, f'a {self._verb()} {_address(tail, tail_iface, addr)} dev {tail_iface}', tail)
 
         self._ip(section, f'l set dev {head_iface} up', head)
         self._ip(section, f'l set dev {tail_iface} up', tail)
 
+        """ Start packet captures, if any, once the interfaces are up and before processes generate traffic. """
+        for nid, iface, attrs in ends:
+            if (capture := attrs.get('capture')):
+                self._add_capture(nid, iface, '' if capture is True else capture)
+
+        """ Attach XDP programs, if any, once the interfaces are up. """
+        for nid, iface, attrs in ends:
+            if (xdp := attrs.get('xdp')) is not None:
+                phynode = self._node_to_phynode(nid)
+                force = '-force ' if self.ensure else ''
+                self._phynode_exec(phynode, section, f'ip {force}-n {nid} l set dev {iface} xdp obj {xdp} || {{ echo "{nid}:{iface}: failed to attach XDP program {xdp}" >&2; false; }}')
+                self._ip(ConfigSection.Down, f'l set dev {iface} xdp off', nid)
+
+        """ Impairment schedules, if any, start with the processes. """
+        for nid, iface, attrs in ends:
+            if (schedule := attrs.get('schedule')) is not None:
+                self._add_schedule(nid, iface, schedule, attrs)
+
+        if self.infra.verify:
+            for nid, iface, attrs in ends:
+                self._verify(nid, iface, attrs.get('mtu'))
+
+        self._observer.on_link(head, head_iface, tail, tail_iface)
+
+    def _add_capture(self, nid: str, iface: str, expression: str):
+
+        """ Capture the packets of interface @p iface of node @p nid in the background.
+            @param[in]  nid         The ID of the node owning the interface.
+            @param[in]  iface       The interface to capture.
+            @param[in]  expression  The filter expression, if any, e.g., 'tcp port 179'.
+            @post                   The capture is written in /var/tmp/dune/<node>/<iface>.pcap and stopped on teardown.
+        """
+
+        phynode = self._node_to_phynode(nid)
+        pcap, pidfile = f'{_captures}/{nid}/{iface}.pcap', f'{_captures}/{nid}/{iface}.pid'
+        expression = f" '{expression}'" if expression else ''
+
+        self._phynode_exec(phynode, ConfigSection.Links, f'mkdir -p {_captures}/{nid}')
+        capture = f'tcpdump -U -i {iface} -w {pcap}{expression} > /dev/null 2>&1 & echo \\$! > {pidfile}'
+        if self.ensure: capture = f'kill -0 \\$(cat {pidfile} 2> /dev/null) 2> /dev/null || {{ {capture}; }}'
+        self._node_exec(nid, ConfigSection.Links, capture)
+        self._phynode_exec(phynode, ConfigSection.Down, self._graceful_down(pidfile, {'signal': 'SIGINT', 'timeout': '5s', 'then': 'SIGKILL'}))
+
+    def _add_schedule(self, nid: str, iface: str, schedule: dict, attrs: dict):
+
+        """ Change the impairments of interface @p iface of node @p nid over time, in the background.
+            @param[in]  nid         The ID of the node owning the interface.
+            @param[in]  iface       The interface to impair.
+            @param[in]  schedule    The steps, each one applying its latency and loss, if any, for its duration, and whether to repeat them.
+            @param[in]  attrs       The link attributes, the latency of the link being the default one of steps.
+            @post                   The schedule runs from the Processes section and is stopped on teardown. The last step is kept
+                                    once a schedule that does not repeat is over.
+        """
+
+        phynode = self._node_to_phynode(nid)
+        pidfile = f'{_run}/{nid}/{iface}.schedule.pid'
+
+        """ The rate of netem is kept, unless the bandwidth is shaped by tbf. """
+        rate = '' if 'bw' in attrs else ' rate 1gbit'
+        steps = []
+        for step in schedule['steps']:
+            loss = f' loss {step["loss"]}' if 'loss' in step else ''
+            steps.append(f'tc qdisc change dev {iface} root handle 1: netem delay {step.get("latency", attrs.get("latency", "0ms"))}{loss}{rate}; sleep {step["duration"]}')
+        steps = '; '.join(steps)
+        if schedule.get('repeat', False): steps = f'while true; do {steps}; done'
+
+        self._add_run_dir(nid)
+        cmd = f'( {steps} ) > /dev/null 2>&1 & echo \\$! > {pidfile}'
+        if self.ensure: cmd = f'kill -0 \\$(cat {pidfile} 2> /dev/null) 2> /dev/null || {{ {cmd}; }}'
+        self._node_exec(nid, ConfigSection.Processes, cmd)
+        self._phynode_exec(phynode, ConfigSection.Down, self._graceful_down(pidfile, {'signal': 'SIGTERM', 'timeout': '5s', 'then': 'SIGKILL'}))
+
+    def _verify(self, nid: str, iface: str, mtu: int = None):
+
+        """ Read back the configuration of interface @p iface of node @p nid.
+            @param[in]  nid     The ID of the node owning the interface.
+            @param[in]  iface   The interface to check.
+            @param[in]  mtu     The expected MTU, if any.
+            @post               A mismatch with the addresses of the node or @p mtu is reported with the actual value.
+        """
+
+        section = ConfigSection.Links
+        phynode = self._node_to_phynode(nid)
+        link = f'ip -n {nid} -o l show dev {iface}'
+        addrs = f'ip -n {nid} -o a show dev {iface}'
+
+        if mtu is not None:
+            self._phynode_exec(phynode, section, f'{link} | grep -q " mtu {mtu} " || {{ echo "{nid}:{iface}: expected mtu {mtu}, got $({link} | grep -o "mtu [0-9]*")" >&2; false; }}')
+
+        for addr in self.topo.nodes[nid]['cfg']._addresses.get(iface, []):
+            try:
+                addr = str(ipaddress.ip_interface(addr))
+            except ValueError:
+                pass
+            self._phynode_exec(phynode, section, f'{addrs} | grep -q " {addr} " || {{ echo "{nid}:{iface}: expected address {addr}, got $({addrs} | awk \'{{print $4}}\' | xargs)" >&2; false; }}')
+
+    def _add_physical(self, nid: str, iface: str, host_iface: str):
+
+        """ Move the physical interface @p host_iface of the phynode into node @p nid as @p iface.
+            @param[in]  nid         The ID of the node receiving the interface.
+            @param[in]  iface       The name of the interface inside the node.
+            @param[in]  host_iface  The name of the interface on the phynode.
+            @post                   The interface is given back to the phynode on teardown.
+        """
+
+        section = ConfigSection.Links
+        phynode = self._node_to_phynode(nid)
+
+        if self.ensure:
+            self._phynode_exec(phynode, section, f'ip -n {nid} l show dev {iface} > /dev/null 2>&1 || {{ {self._retry(f"ip l set dev {host_iface} netns {nid}")} && ip -n {nid} l set dev {host_iface} name {iface}; }}')
+        else:
+            self._phynode_exec(phynode, section, self._retry(f'ip l set dev {host_iface} netns {nid}'))
+            self._on_rollback(phynode, section, f'ip -n {nid} l set dev {host_iface} netns 1')
+            self._ip(section, f'l set dev {host_iface} name {iface}', nid)
+            self._on_rollback(phynode, section, f'ip -n {nid} l set dev {iface} name {host_iface}')
+
+        if (alias := self.topo.aliases.get((nid, iface))) is not None:
+            self._ip(section, f'l set dev {iface} alias {alias}', nid)
+
+        if (addrs := self.topo.nodes[nid]['cfg']._addresses.get(iface)):
+            for addr in addrs:
+                self._ip(section, f'a {self._verb()} {_address(nid, iface, addr)} dev {iface}', nid)
+
+        self._ip(section, f'l set dev {iface} up', nid)
+
+        if self.infra.verify: self._verify(nid, iface)
+
+        """ Give the interface back to the phynode on teardown. """
+        self._ip(ConfigSection.Down, f'l set dev {iface} down', nid)
+        self._ip(ConfigSection.Down, f'l set dev {iface} name {host_iface}', nid)
+        self._ip(ConfigSection.Down, f'l set dev {host_iface} netns 1', nid)
+
     def _add_setup(self, section: ConfigSection):
         if section not in [ConfigSection.Pre, ConfigSection.Post]: return
         setup = self.infra.pre if section == ConfigSection.Pre else self.infra.post
@@ -373,10 +1014,62 @@ class Dune:
                     except KeyError:
                         self._configs[phynode][section] = [cmd]
 
-    def build(self):
+    def build(self, ensure: bool = False, prune: bool = False, only: list = None):
+        """ Generate the config of every phynode.
+            @param[in]  ensure  Reconcile the phynodes instead of creating everything, i.e., existing namespaces, interfaces,
+                                addresses, routes and tracked processes are kept. One-shot execs are run again and there is no rollback.
+            @param[in]  prune   Remove the namespaces previously created by Dune on a phynode that are no longer in the config.
+            @param[in]  only    The IDs of the nodes to set up, if not all of them. Links to other nodes are skipped.
+                                Nodes are still allocated as in a full setup.
+        """
 
-        """ Nodes and Processes hook. """
-        for nid in self.topo.nodes(): self._add_node(nid)
+        try:
+            self._build(ensure, prune, only)
+        except (DuneError, TemplateError) as e:
+            self._observer.on_error(e)
+            raise
+
+    def _build(self, ensure: bool, prune: bool, only: list):
+        """ Generate the config of every phynode, see build(). """
+
+        self.ensure = ensure
+
+        nids = set(self.topo.nodes) if only is None else set(only)
+        if len(unknown := nids - set(self.topo.nodes)) > 0:
+            raise DuneError(f'Unknown nodes {sorted(unknown)}.')
+        if prune and only is not None:
+            raise DuneError('Pruning would remove the namespaces of the nodes that are not set up, it requires a full setup.')
+        if ensure and self.infra.namespaces == 'ephemeral':
+            raise DuneError('Ephemeral namespaces are removed when the setup script exits, they cannot be reconciled.')
+        self._built = nids
+
+        """ Reject conflicting addresses before anything is generated. """
+        self._check_addresses()
+
+        """ Management bridge of each phynode, if any, before nodes are plugged into it. Bridges are joined by a VLAN over the uplinks. """
+        if (management := self.topo.management) is not None:
+            bridge, vlan = management['bridge'], f'dmv{management["vlan"]}'
+            phynodes = sorted(set(self._node_to_phynode(nid) for nid in nids))
+            for phynode in phynodes:
+                create = f'ip l add {bridge} type bridge'
+                self._phynode_exec(phynode, ConfigSection.Nodes, f'ip l show dev {bridge} > /dev/null 2>&1 || {create}' if self.ensure else create)
+                self._on_rollback(phynode, ConfigSection.Nodes, f'ip l del {bridge}')
+                self._phynode_exec(phynode, ConfigSection.Nodes, f'ip a {self._verb()} {self._management_gateway(phynode)} dev {bridge}')
+                self._phynode_exec(phynode, ConfigSection.Nodes, f'ip l set dev {bridge} up')
+                if len(phynodes) > 1:
+                    if (uplink := self.infra.uplinks.get(phynode)) is None:
+                        raise DuneError(f'Phynode <{phynode}> has no \'uplink\' to join the management network of the other phynodes.')
+                    create = self._retry(f'ip l add link {uplink} name {vlan} type vlan id {management["vlan"]}')
+                    self._phynode_exec(phynode, ConfigSection.Nodes, f'ip l show dev {vlan} > /dev/null 2>&1 || {create}' if self.ensure else create)
+                    self._on_rollback(phynode, ConfigSection.Nodes, f'ip l del {vlan}')
+                    self._phynode_exec(phynode, ConfigSection.Nodes, f'ip l set dev {vlan} master {bridge} up')
+                    self._phynode_exec(phynode, ConfigSection.Down, f'ip l del {vlan}')
+                self._phynode_exec(phynode, ConfigSection.Down, f'ip l del {bridge}')
+
+        """ Nodes and Processes hook. Processes of dependencies are launched first. """
+        for nid in self.topo.startup_order():
+            if nid in nids: self._add_node(nid)
+        self._add_teardown(nids)
 
         """ Add links. """
         iface_set = {}
@@ -384,6 +1077,10 @@ class Dune:
             if head not in iface_set: iface_set[head] = []
             if tail not in iface_set: iface_set[tail] = []
             if ifaces[0] not in iface_set[head] and ifaces[1] not in iface_set[tail]:
+                if head not in nids or tail not in nids:
+                    if head in nids:
+                        warn(f'Link <{_link_id(head, ifaces[0], tail, ifaces[1])}> skipped, one of its nodes is not set up.')
+                    continue
                 self._add_link(head, tail, ifaces, link)
                 try:
                     iface_set[head].append(ifaces[0])
@@ -395,90 +1092,347 @@ class Dune:
                 except KeyError:
                     ifaces_set[tail] = [ifaces[1]]
 
+        """ Attach physical interfaces, sorted by name for a reproducible output. """
+        for nid, node in self.topo.nodes(data='cfg'):
+            if nid in nids and node.physical is not None:
+                for iface, host_iface in sorted(node.physical.items()):
+                    self._add_physical(nid, iface, host_iface)
+
+        """ Static routes and policy rules, once every interface is up. They are removed with the netns. """
+        for nid, node in self.topo.nodes(data='cfg'):
+            if nid not in nids: continue
+            for route in node.routes:
+                self._ip(ConfigSection.Links, f'r {self._verb()} {route["to"]} ' + ' '.join(f'{k} {v}' for k, v in route.items() if k != 'to'), nid)
+            for rule in node.rules:
+                rule = ' '.join(f'{k} {v}' for k, v in rule.items())
+                if self.ensure: self._phynode_exec(self._node_to_phynode(nid), ConfigSection.Links, f'ip -n {nid} rule del {rule} 2> /dev/null || true')
+                self._ip(ConfigSection.Links, f'rule add {rule}', nid)
+
+        """ Cgroups and run directories are removed last, once processes, captures and schedules are stopped. """
+        self._add_cleanup(nids)
+
+        """ Record the namespaces of each phynode, pruning the stale ones recorded by previous runs, if requested. """
+        for phynode in self._configs:
+            namespaces = ' '.join(nid for nid, node in self.nodes_on(phynode) if nid in nids and not node.external_ns)
+            if prune:
+                self._phynode_exec(phynode, ConfigSection.Nodes, f'for ns in $(cat {_run}/netns 2> /dev/null); do case " {namespaces} " in *" $ns "*) ;; *) ip netns pids $ns | xargs -r kill || true; ip netns del $ns || true; rmdir {_cgroups}/$ns 2> /dev/null || true; rm -rf {_run}/$ns;; esac; done')
+            self._phynode_exec(phynode, ConfigSection.Nodes, f'mkdir -p {_run} && echo {namespaces} {">" if prune else ">>"} {_run}/netns')
+
+        """ Fail fast if the phynode lacks the privileges to create netns and links. """
+        for phynode in self._configs:
+            self._phynode_exec(phynode, ConfigSection.Pre, _privileges)
+
+        """ Phynode sysctls, applied before the pre-setup commands. """
+        for phynode in self._configs:
+            for sysctl, value in self.infra.sysctls.get(phynode, {}).items():
+                self._phynode_exec(phynode, ConfigSection.Pre, f'sysctl -w {sysctl}={value}')
+
+        """ Ready hook, run once per phynode in its own namespace, e.g., to notify a controller. """
+        for phynode, hook in self.infra.ready.items():
+            if phynode in self._configs:
+                self._phynode_exec(phynode, ConfigSection.Ready, f'{hook} || {{ echo "dune: ready hook of {phynode} failed with status $?" >&2; false; }}')
+
         """ Pre-setup hook. """
         self._add_setup(ConfigSection.Pre)
 
         """ Post-setup hook. """
         self._add_setup(ConfigSection.Post)
 
-    def dump(self, format: str = 'text'):
+    def dump(self, format: str = 'text', mpf_format: str = 'yml', phynodes: list = None):
+        """ Dump the configs of @p phynodes, or of every phynode if None, with the node files and the mpf roles. """
         import os
 
+        if format == 'json' and self.infra.namespaces == 'ephemeral':
+            raise DuneError('Ephemeral namespaces are reverted by the setup script, they require the \'text\' format, i.e., the shell backend.')
+
         base = os.path.join(self.base, '.dune')
         if not os.path.exists(base): os.mkdir(base)
 
         """ Dump phynodes configs """
         for phynode, config in self._configs.items():
+            if phynodes is not None and phynode not in phynodes: continue
             print(phynode, config)
             with open(os.path.join(base, phynode), 'w') as fd:
+                self._dump_config(fd, phynode, format)
 
-                if format == 'text':
-                    for k, v in [
-                        ('# PreSetup', ConfigSection.Pre),
-                        ('# Nodes', ConfigSection.Nodes),
-                        ('# Links', ConfigSection.Links),
-                        ('# PostSetup', ConfigSection.Post),
-                        ('# Processes', ConfigSection.Processes)
-                    ]:
-                        fd.write(f'{k}\n')
-                        for cmd in config[v]: fd.write(f'{cmd}\n')
-
-                elif format == 'json':
-                    import json
-                    json.dump(config, fd)
+        """ Dump topology metadata for tooling, e.g., visualization. """
+        with open(os.path.join(base, 'topology.json'), 'w') as fd:
+            import json
+            nodes = {nid: dict(phynode=self._node_to_phynode(nid), label=node.label, tags=node.tags) for nid, node in self.topo.nodes(data='cfg')}
+            json.dump(dict(nodes=nodes, links=self.links()), fd)
 
         """ Dump templates """
         nodes_dir = os.path.join(base, 'nodes')
         if not os.path.exists(nodes_dir): os.mkdir(nodes_dir)
-        for node, cfg in self.topo.nodes(data=True):
-            cfg = cfg['cfg']
-            targets = {}
-            node_dir = os.path.join(nodes_dir, node)
-            if not os.path.exists(node_dir): os.mkdir(node_dir)
-            for template, data in cfg.templates.items():
-                local = os.path.basename(data['dst'])
-                targets[local] = data['dst']
-                print(node, template, data)
-                dst = os.path.join(node_dir, local)
-                with open(dst, 'w') as fp:
-                    fp.write(data['content'])
-            with open(os.path.join(node_dir, 'targets.yml'), 'w') as fd:
-                yaml.dump(targets, fd)
+        for node in self.topo.nodes:
+            if node in self._built: self._dump_node(nodes_dir, node)
 
         """ Dump roles for mpf """
-        seen = []
-        roles = {}
-        for head, tail, (head_iface, tail_iface),  in self.topo.edges(keys=True):
-            forward = f'{head}:{head_iface}-{tail}:{tail_iface}'
-            reverse = f'{tail}:{tail_iface}-{head}:{head_iface}'
-            if reverse in seen: continue
-            seen.append(forward)
-
-            """ Add forward direction """
-            a = (forward, 'forward')
-            try:
-                roles[head][head_iface] = a
-            except KeyError:
-                roles[head] = {head_iface: a}
+        name = sub('\.dune\.yml', '', self.name)
+        with open(os.path.join(self.base, f'{name}.mpf.{mpf_format}'), 'w') as fd:
+            if mpf_format == 'yml':
+                yaml.dump(self.mpf_roles(), fd)
+            elif mpf_format == 'json':
+                import json
+                json.dump(self.mpf_roles(), fd)
+
+    def _dump_config(self, fd, phynode: str, format: str):
+        """ Write the config of @p phynode in @p fd, i.e., a shell script for the 'text' @p format or every section for 'json'. """
+
+        config = self._configs.get(phynode, {})
+
+        """ Sections are always written in execution order, even if empty. """
+        if format == 'text':
+
+            """ On error, revert the resources created so far by the script, in reverse order. """
+            undo = self._undo.get(phynode, {})
+            ephemeral = self.infra.namespaces == 'ephemeral'
+            if self.infra.rollback_on_error or ephemeral:
+                fd.write('# Rollback\n')
+                fd.write('undo=\n')
+            if self.infra.rollback_on_error:
+                fd.write('rollback() { set +e; trap - EXIT; echo "dune: setup failed, rolling back" >&2; eval "$undo"; exit 1; }\n')
+                fd.write('trap rollback ERR\n')
+                fd.write('set -e\n')
+
+            """ Ephemeral namespaces live as long as the script, which reverts everything it created when it exits or is interrupted. """
+            if ephemeral:
+                fd.write('trap \'set +e; trap - ERR; eval "$undo"\' EXIT\n')
+                fd.write('trap \'exit 0\' INT TERM\n')
+
+            """ Only setup sections, teardown is not part of the script. """
+            for section in [s for s in ConfigSection if s not in [ConfigSection.PreDown, ConfigSection.Down]]:
+                fd.write(f'# {section}\n')
+                for idx, cmd in enumerate(config.get(section, [])):
+                    fd.write(f'{cmd}\n')
+                    if (self.infra.rollback_on_error or ephemeral) and (section, idx) in undo:
+                        fd.write(f'undo="{undo[section, idx]}; $undo"\n')
+
+            if ephemeral:
+                fd.write('# Ephemeral\n')
+                fd.write('echo "dune: set up, namespaces are removed when this script exits" >&2\n')
+                fd.write('while sleep 1; do :; done\n')
+
+        elif format == 'json':
+            import json
+            json.dump({section: config.get(section, []) for section in ConfigSection}, fd)
+
+    def _dump_node(self, nodes_dir: str, node: str) -> dict:
+        """ Write the rendered templates, hosts file and binds of @p node in @p nodes_dir/<node>, with their targets.yml.
+            @return     The targets, i.e., the destination in the node of each written file.
+        """
 
-            """ Add reverse direction """
-            b = (forward, 'backward')
-            try:
-                roles[tail][tail_iface] = b
-            except KeyError:
-                roles[tail] = {tail_iface: b}
+        cfg = self.topo.nodes[node]['cfg']
+        targets = {}
+        node_dir = os.path.join(nodes_dir, node)
+        if not os.path.exists(node_dir): os.makedirs(node_dir)
+        for template, data in (cfg.templates or {}).items():
+            local = os.path.basename(data['dst'])
+            targets[local] = data['dst']
+            print(node, template, data)
+            dst = os.path.join(node_dir, local)
+            with open(dst, 'w') as fp:
+                fp.write(data['content'])
+
+        """ 'ip netns exec' bind-mounts /etc/netns/<node>/hosts over /etc/hosts. """
+        if self.topo.generate_hosts:
+            targets['hosts'] = f'/etc/netns/{node}/hosts'
+            with open(os.path.join(node_dir, 'hosts'), 'w') as fp:
+                fp.write(self._hosts())
+
+        """ Binds are copied as is, directories recursively, preserving permissions, e.g., exec bits. """
+        for src, dst in cfg.binds.items():
+            local = os.path.basename(dst.rstrip('/'))
+            if local in targets:
+                raise DuneError(f'Bind <{src}> of node <{node}> conflicts with <{targets[local]}>.')
+            targets[local] = dst
+            src = os.path.join(self.base, src)
+            if os.path.isdir(src):
+                shutil.copytree(src, os.path.join(node_dir, local), dirs_exist_ok=True)
+            else:
+                shutil.copy2(src, os.path.join(node_dir, local))
+
+        with open(os.path.join(node_dir, 'targets.yml'), 'w') as fd:
+            yaml.dump(targets, fd)
+
+        return targets
+
+    def export_bundle(self, phynode: str, path: str, compress: bool = False) -> str:
+        """ Write in @p path everything needed to set up @p phynode offline, e.g., without access to the config.
+            @param[in]  phynode     The phynode to export.
+            @param[in]  path        The bundle directory, created if needed.
+            @param[in]  compress    Whether to write the bundle as the gzipped tarball @p path.tar.gz instead, e.g., to ship large node files.
+                                    The directory @p path is then neither created nor modified.
+            @pre        The topology is built.
+            @post       The bundle holds setup.sh, the setup script, config.json, every section including teardown, nodes/<node>, the
+                        files of each node of @p phynode, and manifest.yml, listing them with the destination of each node file.
+            @return     The path of the bundle, i.e., @p path, or @p path.tar.gz if compressed.
+        """
+
+        if phynode not in self.infra._cores:
+            raise DuneError(f'Unknown phynode <{phynode}>, known phynodes are {list(self.infra._cores)}.')
+
+        if not compress:
+            self._write_bundle(phynode, path)
+            return path
+
+        """ The tarball is built from a scratch directory, leaving whatever is at @p path untouched. """
+        os.makedirs(os.path.dirname(os.path.abspath(path)), exist_ok=True)
+        with tempfile.TemporaryDirectory() as scratch:
+            self._write_bundle(phynode, scratch)
+            return shutil.make_archive(path, 'gztar', root_dir=scratch)
+
+    def _write_bundle(self, phynode: str, path: str):
+        """ Write the bundle of @p phynode in the directory @p path, created if needed, see export_bundle(). """
+
+        os.makedirs(path, exist_ok=True)
+
+        with open(os.path.join(path, 'setup.sh'), 'w') as fd:
+            self._dump_config(fd, phynode, 'text')
+        with open(os.path.join(path, 'config.json'), 'w') as fd:
+            self._dump_config(fd, phynode, 'json')
+
+        nodes = {nid: self._dump_node(os.path.join(path, 'nodes'), nid) for nid in self.nodes_of_phynode(phynode) if nid in self._built}
+        with open(os.path.join(path, 'manifest.yml'), 'w') as fd:
+            yaml.dump(dict(phynode=phynode, topology=self.name, setup='setup.sh', config='config.json', nodes=nodes), fd)
+
+    def cleanup_phynode(self, phynode: str) -> list:
+        """ Return the commands wiping, by name, everything Dune may have created on @p phynode, e.g., after a run died mid-setup.
+            The topology needs not be built, the nodes of @p phynode being derived from the allocation.
+            @param[in]  phynode The phynode to clean up.
+            @return     The shell commands, in order. Each one reports what it removed and ignores what is already gone.
+                        External namespaces are kept, only the interfaces created by Dune in them being removed, i.e., the link
+                        endpoints, veths, VLANs and tunnels alike, and the management interface, and their physical ones given back.
+        """
+
+        if phynode not in self.infra._cores:
+            raise DuneError(f'Unknown phynode <{phynode}>, known phynodes are {list(self.infra._cores)}.')
+
+        cmds = []
+        for nid, node in self.nodes_on(phynode):
+
+            """ Processes of the node, then its namespace. Interfaces in the namespace go with it, physical ones back to the phynode. """
+            cmds.append(f'for pidfile in {_run}/{nid}/*.pid; do [ -e $pidfile ] && kill $(cat $pidfile) 2> /dev/null && echo "dune: killed $pidfile"; done; true')
+            if node.external_ns:
+                ifaces = sorted(set(iface for _, _, (iface, _) in self.topo.edges(nid, keys=True)))
+                if self.topo.management is not None: ifaces.append(self.topo.management['iface'])
+                for iface in ifaces:
+                    cmds.append(f'ip -n {nid} l del {iface} 2> /dev/null && echo "dune: removed {nid}:{iface}"; true')
+                for iface, host_iface in (node.physical or {}).items():
+                    cmds.append(f'ip -n {nid} l set dev {iface} name {host_iface} 2> /dev/null && ip -n {nid} l set dev {host_iface} netns 1 2> /dev/null && echo "dune: moved {nid}:{iface} back to {host_iface}"; true')
+            else:
+                cmds.append(f'[ -e /var/run/netns/{nid} ] && ip netns pids {nid} | xargs -r kill; ip netns del {nid} 2> /dev/null && echo "dune: removed netns {nid}"; true')
+                for iface, host_iface in (node.physical or {}).items():
+                    cmds.append(f'ip l show dev {iface} > /dev/null 2>&1 && ip l set dev {iface} name {host_iface} && echo "dune: renamed {iface} back to {host_iface}"; true')
+            cgroup = f'{_cgroups}/{nid}'
+            cmds.append(f'[ -d {cgroup} ] && {{ echo 1 > {cgroup}/cgroup.kill; while [ -s {cgroup}/cgroup.procs ]; do sleep 0.1; done; rmdir {cgroup} && echo "dune: removed cgroup {nid}"; }}; true')
+            cmds.append(f'[ -d {_run}/{nid} ] && rm -rf {_run}/{nid} && echo "dune: removed {_run}/{nid}"; true')
+
+        if (management := self.topo.management) is not None:
+            cmds.append(f'ip l del dmv{management["vlan"]} 2> /dev/null && echo "dune: removed management VLAN dmv{management["vlan"]}"; true')
+            cmds.append(f'ip l del {management["bridge"]} 2> /dev/null && echo "dune: removed bridge {management["bridge"]}"; true')
+        cmds.append(f'rm -f {_run}/netns')
+
+        return cmds
+
+    def links(self) -> list:
+
+        """ Return the links of the topology, each one once with both of its endpoints resolved.
+            @return     A list of dicts with the canonical link 'id', its 'label' and 'tags', and its two 'endpoints'. Each endpoint gives
+                        its 'node', 'phynode', 'iface', 'addrs', 'latency', 'bw' and raw link 'attributes'.
+        """
+
+        links = []
+        for head, tail, (head_iface, tail_iface), data in self.topo.edges(keys=True, data=True):
+
+            """ Each link is visited once, starting from its smallest endpoint. """
+            if (head, head_iface) > (tail, tail_iface): continue
+
+            endpoints = []
+            for nid, iface, attrs in [(head, head_iface, data), (tail, tail_iface, self.topo.edges[tail, head, (tail_iface, head_iface)])]:
+                endpoints.append(dict(
+                    node=nid,
+                    phynode=self._node_to_phynode(nid),
+                    iface=iface,
+                    addrs=self.topo.nodes[nid]['cfg']._addresses.get(iface, []),
+                    latency=attrs.get('latency', '0ms'),
+                    bw=bandwidth(attrs.get('bw', '1gbit'))['rate'],
+                    attributes=attrs,
+                ))
+            links.append(dict(
+                id=_link_id(head, head_iface, tail, tail_iface),
+                label=data.get('label'),
+                tags=data.get('tags', []),
+                endpoints=endpoints,
+            ))
+
+        return links
+
+    def path_metric(self, src: str, dst: str) -> int:
+
+        """ Return the cost of the shortest path from node @p src to node @p dst, e.g., to predict the routes of a routing protocol.
+            Each link costs the 'metric' of its interface on the sending side, 1 by default.
+            @return     The cost, or None if @p dst is not reachable from @p src.
+        """
+
+        def cost(head: str, tail: str, links: dict) -> int:
+            return min(data.get('metric', 1) for data in links.values())
+
+        try:
+            return networkx.dijkstra_path_length(self.topo, src, dst, weight=cost)
+        except (networkx.NetworkXNoPath, networkx.NodeNotFound):
+            return None
+
+    def cross_phynode_links(self) -> list:
+
+        """ Return the links whose endpoints lie on different phynodes, e.g., to plan an overlay. Tunnels, over the underlay, are excluded.
+            @return     The links, as returned by links(), with the 'phynodes' of both endpoints and the 'vlan' ID, once built.
+        """
+
+        return [dict(link, phynodes=phynodes, vlan=self._vlans.get(link['id'])) for link in self.links()
+                if (phynodes := tuple(endpoint['phynode'] for endpoint in link['endpoints']))[0] != phynodes[1]
+                and link['endpoints'][0]['attributes'].get('kind', 'veth') == 'veth']
+
+    def to_dot(self) -> str:
+
+        """ Return the topology as an undirected Graphviz DOT graph.
+            Nodes are labeled with their ID and phynode, links with their latency and bandwidth.
+        """
 
-        r = [{'role': role, 'namespace': role, 'interfaces': [{'name': iface, 'link': name, 'direction': ord} for iface, (name, ord) in ifaces.items()]} for role, ifaces in roles.items()]
         name = sub('\.dune\.yml', '', self.name)
-        with open(os.path.join(self.base, f'{name}.mpf.yml'), 'w') as fd:
-            yaml.dump(r, fd)
+        dot = [f'graph "{name}" {{']
+        for nid in self.topo.nodes:
+            dot.append(f'    "{nid}" [label="{nid}\\n{self._node_to_phynode(nid)}"];')
+
+        for link in self.links():
+            head, tail = link['endpoints']
+            latency = head['latency'] if head['latency'] == tail['latency'] else f'{head["latency"]}/{tail["latency"]}'
+            bw = head['bw'] if head['bw'] == tail['bw'] else f'{head["bw"]}/{tail["bw"]}'
+            dot.append(f'    "{head["node"]}" -- "{tail["node"]}" [label="{latency}, {bw}", taillabel="{head["iface"]}", headlabel="{tail["iface"]}"];')
+
+        dot.append('}')
+        return '\n'.join(dot) + '\n'
+
+    def mpf_roles(self) -> list:
+
+        """ Return the mpf roles, one per node, with the link and direction of each of its interfaces. """
+
+        """ Nodes without links still get their namespace. """
+        roles = {nid: {} for nid in self.topo.nodes}
+        for link in self.links():
+            head, tail = link['endpoints']
+            roles[head['node']][head['iface']] = (link['id'], 'forward')
+            roles[tail['node']][tail['iface']] = (link['id'], 'backward')
+
+        return [{'role': role, 'namespace': role, 'interfaces': [{'name': iface, 'link': name, 'direction': ord} for iface, (name, ord) in ifaces.items()]} for role, ifaces in roles.items()]
 
 def cli():
     from pathlib import Path
     import argparse
 
     parser = argparse.ArgumentParser()
-    parser.add_argument('-t', '--topology', type=Path, required=True, help='Topology definition file')
+    parser.add_argument('-t', '--topology', type=str, required=True, help='Topology definition file, \'-\' for stdin or an http(s) URL, see --allow-remote')
+    parser.add_argument('--allow-remote', action='store_true', help='Allow fetching the topology and its includes over http(s), as does DUNE_ALLOW_REMOTE=1')
     parser.add_argument('-b', '--backend', type=str, default='mpf', choices=['mpf', 'shell'], help="""
     Backend used to apply the topology.
 
@@ -486,14 +1440,57 @@ def cli():
 
     shell: Produce a shell script per phynode that users have to manually transfer and execute.
     """)
+    parser.add_argument('--profile', type=str, action='append', dest='profiles', help='Include the nodes and links of this profile, can be repeated')
+    parser.add_argument('--ensure', action='store_true', help='Reconcile the phynodes with the topology, only creating what is missing')
+    parser.add_argument('--prune', action='store_true', help='Remove the namespaces created by previous runs that are no longer in the topology')
+    parser.add_argument('--phynode', type=str, nargs='?', const='', help='Only dump the config of this phynode, taken from $DUNE_PHYNODE or detected from the hostname if no name is given')
+    parser.add_argument('--only-node', type=str, action='append', dest='only_nodes', help='Only set up this node, can be repeated')
+    parser.add_argument('--only-phynode', type=str, action='append', dest='only_phynodes', help='Only set up the nodes of this phynode, can be repeated')
+    parser.add_argument('--bundle', type=str, help='Also export a bundle per dumped phynode in this directory, to set it up offline')
+    parser.add_argument('--compress', action='store_true', help='Export each bundle of --bundle as a gzipped tarball')
+    parser.add_argument('--validate', action='store_true', help='Only report the problems of the topology, failing on errors')
+    parser.add_argument('--cleanup', action='store_true', help='Only print the commands wiping what Dune may have created on the phynode of --phynode')
+    parser.add_argument('--mpf-format', type=str, default='yml', choices=['yml', 'json'], help='Format of the mpf roles file')
     args = parser.parse_args()
 
-    base = args.topology.parent
-    topo = args.topology.name
-
-    dune = Dune(base, topo)
-    dune.build()
-    dune.dump(format='json')
-
-if __name__ == 'dune' or __name__ == '__main__':
+    if args.allow_remote: os.environ['DUNE_ALLOW_REMOTE'] = '1'
+
+    """ Outputs of a config read from stdin or an URL are written in the working directory. """
+    if args.topology == '-' or is_remote(args.topology):
+        base, topo = Path('.'), args.topology
+    else:
+        base, topo = Path(args.topology).parent, Path(args.topology).name
+
+    if args.validate:
+        diagnostics = Dune.validate(base, topo, args.profiles)
+        for diagnostic in diagnostics:
+            print(f'{diagnostic["severity"]}: {diagnostic["message"]}')
+        exit(1 if any(diagnostic['severity'] == 'error' for diagnostic in diagnostics) else 0)
+
+    try:
+        dune = Dune(base, topo, args.profiles)
+        only = None
+        if args.only_nodes is not None or args.only_phynodes is not None:
+            only = list(args.only_nodes or [])
+            for phynode in args.only_phynodes or []:
+                if phynode not in dune.infra._cores:
+                    raise DuneError(f'Unknown phynode <{phynode}>, known phynodes are {list(dune.infra._cores)}.')
+                only.extend(dune.nodes_of_phynode(phynode))
+        if args.cleanup:
+            """ Only the commands go to stdout, e.g., to be piped into sh, warnings of the allocation going to stderr. """
+            with redirect_stdout(sys.stderr):
+                cmds = dune.cleanup_phynode(dune.infra.local(args.phynode or None))
+            print('\n'.join(cmds))
+            exit(0)
+        dune.build(ensure=args.ensure, prune=args.prune, only=only)
+        phynodes = None if args.phynode is None else [dune.infra.local(args.phynode or None)]
+        dune.dump(format='text' if args.backend == 'shell' else 'json', mpf_format=args.mpf_format, phynodes=phynodes)
+        if args.bundle is not None:
+            for phynode in phynodes or dune.infra._cores:
+                dune.export_bundle(phynode, os.path.join(args.bundle, phynode), compress=args.compress)
+    except DuneError as e:
+        print(e)
+        exit(1)
+
+if __name__ == '__main__':
     cli()
@@ -0,0 +1,3 @@
+from dune import cli
+
+cli()
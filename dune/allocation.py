@@ -0,0 +1,156 @@
+from collections import Counter
+from copy import deepcopy
+
+from dune.errors import DuneError, warn
+from dune.infrastructure import Infra
+from dune.topology import Node, Topo
+
+class Allocator:
+    """
+    Allocation of the cores of the phynodes to the pinned processes of the nodes.
+    Subclass it to plug another scheduling algorithm, e.g., bin-packing or ILP-based.
+    """
+
+    def _place(self, infra: Infra, nid: str, node: Node, available: dict) -> tuple:
+
+        """ Reserve the cores of the explicit placement of node @p nid.
+            @param[in]  infra       The infrastructure providing the cores.
+            @param[in]  nid         The node id.
+            @param[in]  node        The node config, with a placement.
+            @param[in]  available   The available cores of each phynode, per NUMA node. The placed cores, and their siblings unless
+                                    the SMT policy is 'split', are withdrawn.
+            @return     The allocation of the node. DuneError is raised if a placed core is not available.
+        """
+
+        phynode, cores = node.placement['phynode'], list(node.placement['cores'])
+        if phynode not in available:
+            raise DuneError(f'Node <{nid}> is placed on unknown phynode <{phynode}>.')
+        if len(cores) != node._get_n_cores():
+            raise DuneError(f'Node <{nid}> requires {node._get_n_cores()} cores but {len(cores)} are placed.')
+
+        for core in cores:
+            block = next((block for block in available[phynode] if type(block) == list and core in block), None)
+            if block is None:
+                raise DuneError(f'Core {core} of phynode <{phynode}> placed for node <{nid}> is not available.')
+            block.remove(core)
+
+        if infra.smt != 'split':
+            for sibling in [s for core in cores for s in infra.siblings.get(phynode, {}).get(core, [])]:
+                for block in available[phynode]:
+                    if type(block) == list and sibling in block: block.remove(sibling)
+
+        return (phynode, [[cores.pop(0) for _ in process] for process in node._get_cores()])
+
+    def allocate(self, topo: Topo, infra: Infra) -> dict:
+        """ Allocate the nodes of @p topo on the phynodes of @p infra.
+            @param[in]  topo    The topology whose nodes have to be allocated.
+            @param[in]  infra   The infrastructure providing the cores.
+            @return     The allocation, i.e., {nid: (phynode, [[cores of process 0], [cores of process 1], ...])}, or None on error.
+        """
+        raise NotImplementedError
+
+class Greedy(Allocator):
+    """ Default allocator. Nodes requiring the most cores first take them from the first NUMA node large enough. """
+
+    def allocate(self, topo: Topo, infra: Infra) -> dict:
+        # TODO: clever way with buckets and CP, fill gaps if any
+
+        allocation = {}
+
+        available = deepcopy(infra._cores)
+
+        for nid, node in topo.nodes(data='cfg'):
+            if node.phynode is not None and node.phynode not in available:
+                raise DuneError(f'Node <{nid}> is assigned to unknown phynode <{node.phynode}>.')
+
+        """ Explicitly placed nodes reserve their cores first. """
+        for nid, node in topo.nodes(data=True):
+            if node['cfg'].placement is not None:
+                allocation[nid] = self._place(infra, nid, node['cfg'], available)
+
+        for node, count in sorted(
+                {nid: n for nid, node in topo.nodes(data=True) if nid not in allocation and (n := node['cfg']._get_n_cores()) > 0}.items(),
+                key=lambda item: (topo.nodes[item[0]]['cfg'].phynode is not None, item[1]),
+                reverse=True):
+
+            alloc = []
+            phynode0 = None
+            spare = {}
+            for process in topo.nodes[node]['cfg']._get_cores():
+                b = False
+                for phynode, cores in available.items():
+                    if b: break
+                    if (target := topo.nodes[node]['cfg'].phynode) is not None and phynode != target: continue
+                    for numa in cores:
+                        # TODO: use list:
+                        if type(numa) != list: continue
+                        if len(numa) >= count:
+                            alloc.append([self._pop_core(infra, phynode, cores, numa, spare.setdefault(phynode, [])) for _ in process])
+                            b = True
+                            phynode0 = phynode
+                            break
+
+            if phynode0 is None:
+                phynode0, alloc = self._spread(infra, node, topo.nodes[node]['cfg'], count, available)
+            allocation[node] = (phynode0, alloc)
+
+        """ Nodes without pinned processes require no cores, they join their phynode, if assigned, else most of their neighbors. """
+        for nid, node in topo.nodes(data='cfg'):
+            if nid not in allocation and node.phynode is not None: allocation[nid] = (node.phynode, [])
+
+        idle = [nid for nid in topo.nodes if nid not in allocation]
+        if len(idle) > 0: warn(f'Nodes {idle} request no cores, they are placed next to their neighbors.')
+        for nid in idle:
+            neighbors = Counter(allocation[peer][0] for peer in topo.successors(nid) if peer in allocation)
+            allocation[nid] = (neighbors.most_common(1)[0][0] if len(neighbors) > 0 else next(iter(infra._cores)), [])
+
+        return allocation
+
+    def _spread(self, infra: Infra, nid: str, node: Node, count: int, available: dict) -> tuple:
+
+        """ Allocate node @p nid, that no single NUMA node can hold, across the NUMA nodes of a phynode, if the infrastructure allows it.
+            @param[in]  infra       The infrastructure providing the cores.
+            @param[in]  nid         The node id.
+            @param[in]  node        The node config.
+            @param[in]  count       The number of cores required by the node.
+            @param[in]  available   The available cores of each phynode, per NUMA node.
+            @return     The phynode and the cores of each process, taken from the largest NUMA nodes first.
+                        DuneError is raised if the NUMA policy is strict or if no phynode has enough free cores.
+        """
+
+        candidates = [phynode for phynode, cores in available.items() if (node.phynode is None or phynode == node.phynode)
+                      and sum(len(numa) for numa in cores if type(numa) == list) >= count]
+        if infra.numa == 'strict' or len(candidates) == 0:
+            hint = ', set \'numa: spread\' to allocate it across NUMA nodes' if infra.numa == 'strict' and len(candidates) > 0 else ''
+            raise DuneError(f'Node <{nid}> requires {count} cores but no NUMA node has them free{hint}.')
+
+        phynode, spare = candidates[0], []
+        cores = available[phynode]
+        warn(f'Node <{nid}> is allocated across several NUMA nodes of <{phynode}>.')
+        return phynode, [[self._pop_core(infra, phynode, cores, max((numa for numa in cores if type(numa) == list), key=len), spare)
+                          for _ in process] for process in node._get_cores()]
+
+    def _pop_core(self, infra: Infra, phynode: str, cores: list, numa: list, spare: list) -> int:
+
+        """ Take a core from @p numa for a node, honoring the SMT policy of the infrastructure.
+            @param[in]  infra   The infrastructure providing the cores.
+            @param[in]  phynode The phynode owning the cores.
+            @param[in]  cores   The available cores of @p phynode, per NUMA node.
+            @param[in]  numa    The available cores of the selected NUMA node.
+            @param[in]  spare   The siblings of the cores already taken by the node on @p phynode.
+            @return     The core taken. Its siblings are withdrawn from @p cores so that no other node gets them.
+        """
+
+        smt = infra.smt
+        if smt == 'colocate' and len(spare) > 0: return spare.pop(0)
+
+        core = numa.pop()
+        if smt == 'split': return core
+
+        for sibling in infra.siblings.get(phynode, {}).get(core, []):
+            for block in cores:
+                if sibling in block:
+                    block.remove(sibling)
+                    if smt == 'colocate': spare.append(sibling)
+
+        return core
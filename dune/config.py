@@ -0,0 +1,98 @@
+from re import compile
+from urllib.parse import urljoin
+from urllib.request import urlopen
+import os
+import sys
+import yaml
+
+from dune.errors import DuneError
+
+_var = compile(r'(\$?)\$\{env:([A-Za-z_][A-Za-z0-9_]*)\}')
+""" Environment variable reference, i.e., ${env:VAR}, distinct from Dune variables, e.g., ${node}. A leading '$' escapes it, i.e.,
+    $${env:VAR} is kept as ${env:VAR}.
+"""
+
+_stdin = None
+""" Configuration read from stdin, cached as both the topology and the infrastructure are loaded from it. """
+
+def is_remote(path: str) -> bool:
+    """ Whether @p path is an http(s) URL. """
+    return str(path).startswith(('http://', 'https://'))
+
+def _read(path: str) -> str:
+    """ Read the configuration @p path, i.e., a file, '-' for stdin or an http(s) URL if $DUNE_ALLOW_REMOTE is 1. """
+    global _stdin
+
+    if path == '-':
+        if _stdin is None: _stdin = sys.stdin.read()
+        return _stdin
+
+    """ Fetching a config over the network is opt-in, as it may then run plugin calls and commands from a third party. """
+    if is_remote(path) and os.environ.get('DUNE_ALLOW_REMOTE') != '1':
+        raise DuneError(f'Refusing to fetch remote config <{path}>, pass --allow-remote or set DUNE_ALLOW_REMOTE=1 to allow it.')
+
+    try:
+        if is_remote(path):
+            with urlopen(path) as fd:
+                return fd.read().decode()
+        with open(path, 'r') as fd:
+            return fd.read()
+    except OSError as e:
+        raise DuneError(f'Cannot read config <{path}>: {e}.')
+
+def _interpolate(raw: str) -> str:
+    """ Substitute environment variables referenced in @p raw. Other ${...} references, e.g., Dune variables, are kept as is. """
+
+    undefined = set()
+
+    def _sub(m):
+        escape, var = m.groups()
+        if escape: return m.group(0)[1:]
+        if var not in os.environ:
+            undefined.add(var)
+            return m.group(0)
+        return os.environ[var]
+
+    raw = _var.sub(_sub, raw)
+    if len(undefined) > 0:
+        raise DuneError(f'Undefined environment variables referenced in config: {sorted(undefined)}.')
+
+    return raw
+
+def _merge(base: dict, other: dict) -> dict:
+    """ Deep-merge @p other into @p base, values of @p other taking precedence. """
+
+    for key, value in other.items():
+        if isinstance(base.get(key), dict) and isinstance(value, dict):
+            _merge(base[key], value)
+        else:
+            base[key] = value
+
+    return base
+
+def load(path: str, _included: list = None) -> dict:
+    """ Load the configuration file @p path, after environment variables interpolation.
+        @p path can also be '-' for stdin or an http(s) URL.
+        Files listed in its 'include' section, relative to @p path, are deep-merged first, in order.
+        Later files override earlier ones and @p path overrides them all. Includes of stdin are relative to the working directory.
+    """
+
+    if path != '-' and not is_remote(path): path = os.path.realpath(path)
+    included = [] if _included is None else _included
+    if path in included:
+        raise DuneError(f'Include cycle detected: {" -> ".join(included + [path])}.')
+
+    try:
+        cfg = yaml.load(_interpolate(_read(path)), yaml.Loader) or {}
+    except yaml.MarkedYAMLError as e:
+        mark, context = e.problem_mark, f' {e.context}' if e.context else ''
+        raise DuneError(f'Malformed config <{path}>, line {mark.line + 1}, column {mark.column + 1}: {e.problem}{context}.')
+    except yaml.YAMLError as e:
+        raise DuneError(f'Malformed config <{path}>: {e}.')
+
+    merged = {}
+    for include in cfg.pop('include', []):
+        include = urljoin(path, include) if is_remote(path) else os.path.join('' if path == '-' else os.path.dirname(path), include)
+        merged = _merge(merged, load(include, included + [path]))
+
+    return _merge(merged, cfg)
@@ -0,0 +1,22 @@
+from contextlib import contextmanager
+
+class DuneError(Exception):
+    """ Error in the configuration or the setup of a topology. The message describes the faulty element. """
+
+_collectors = []
+""" Lists recording the warnings reported while they are active, see collect(). """
+
+def warn(message: str):
+    """ Report the non-fatal problem @p message, i.e., print it and record it in the active collectors, if any. """
+    print(message)
+    for collector in _collectors: collector.append(message)
+
+@contextmanager
+def collect():
+    """ Record the warnings reported within the context in the yielded list. """
+    warnings = []
+    _collectors.append(warnings)
+    try:
+        yield warnings
+    finally:
+        _collectors.remove(warnings)
@@ -0,0 +1,31 @@
+""" Jinja filters for address computations in templates. Addresses are strings, with or without prefix. """
+
+import ipaddress
+
+def network(addr: str) -> str:
+    """ Return the network of @p addr, e.g., 10.0.0.1/24 -> 10.0.0.0/24. """
+    return str(ipaddress.ip_interface(addr).network)
+
+def host(addr: str, n: int = 1) -> str:
+    """ Return the @p n-th address of the network of @p addr, without prefix, e.g., 10.0.0.7/24 -> 10.0.0.1. """
+    net = ipaddress.ip_interface(addr).network
+    if n >= net.num_addresses:
+        raise ValueError(f'{net} has no address #{n}')
+    return str(net.network_address + n)
+
+def prefixlen(addr: str) -> int:
+    """ Return the prefix length of @p addr, e.g., 10.0.0.1/24 -> 24. """
+    return ipaddress.ip_interface(addr).network.prefixlen
+
+def add(addr: str, n: int) -> str:
+    """ Return @p addr shifted by @p n, keeping its prefix if any, e.g., 10.0.0.1/31 -> 10.0.0.2/31 with n = 1. """
+    iface = ipaddress.ip_interface(addr)
+    shifted = iface.ip + n
+    return f'{shifted}/{iface.network.prefixlen}' if '/' in str(addr) else str(shifted)
+
+FILTERS = {
+    'network': network,
+    'host': host,
+    'prefixlen': prefixlen,
+    'add': add,
+}
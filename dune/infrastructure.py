@@ -1,27 +1,74 @@
-import yaml
+import os
+import socket
+
+from dune.config import load
+from dune.errors import DuneError
+
+def _expand_cores(block) -> list:
+    """ Expand a block of cores given either as a list of integers or as a range string, e.g., '0-15,32-47'.
+        @return     The list of cores of the block, or None if it is malformed.
+    """
+    if type(block) == list: return block
+    if type(block) != str: return None
+
+    cores = []
+    try:
+        for part in block.split(','):
+            first, _, last = part.strip().partition('-')
+            cores.extend(range(int(first), int(last if last else first) + 1))
+    except ValueError:
+        return None
+    return cores
 
 class Infra:
 
     def __init__(self, path: str):
-        with open(path, 'r') as fd:
-            cfg = yaml.load(fd, yaml.Loader) 
+        cfg = load(path)
 
         """ Get mandatory sections. """
         infra = cfg.get('infrastructure')
         if infra is None:
-            print('\'infrastructure\' section not found in config.')
-            exit(1)
+            raise DuneError('\'infrastructure\' section not found in config.')
 
         nodes = infra.get('nodes')
         if nodes is None:
-            print('\'nodes\' section not found in infrastructure.')
-            exit(1)
+            raise DuneError('\'nodes\' section not found in infrastructure.')
 
         self._total_cores = 0
         self._cores = {}
+        """ Cores of each phynode, per NUMA node, as configured. Allocation does not consume them, see Dune.free_cores(). """
+        self.sysctls = {}
+        """ Sysctls, if any, to apply once on each phynode before nodes are created. """
+        self.siblings = {}
+        """ SMT siblings of each core, per phynode, if specified. """
+        self.ready = {}
+        """ Command, if any, to run once on each phynode after every process is started. """
+        self.uplinks = {}
+        """ Physical interface, if any, of each phynode carrying the links to other phynodes. """
+        self.numa_ids = {}
+        """ NUMA node id of each block of cores of each phynode, i.e., its 'numa' label, else its position. """
+
+        self.smt = infra.get('smt', 'isolate')
+        """
+        Allocation policy for SMT siblings.
+        isolate: siblings of the cores of a node are not allocated.
+        colocate: siblings of the cores of a node are allocated to the same node first.
+        split: siblings are ignored and may be allocated to different nodes.
+        """
+        if self.smt not in ['isolate', 'colocate', 'split']:
+            raise DuneError('\'smt\' should be one of \'isolate\', \'colocate\' or \'split\'.')
+
+        self.numa = infra.get('numa', 'strict')
+        """
+        Allocation policy for nodes that no single NUMA node can hold.
+        strict: such nodes are not allocated.
+        spread: such nodes are allocated across the NUMA nodes of a phynode, with a warning.
+        """
+        if self.numa not in ['strict', 'spread']:
+            raise DuneError('\'numa\' should be either \'strict\' or \'spread\'.')
 
         """ Parse mandatory sections. """
-        if self._load_nodes(nodes) != 0: exit(1)
+        self._load_nodes(nodes)
 
         self.pre = None
         """ List of commands to launch at phynode initialization. """
@@ -37,38 +84,110 @@ class Infra:
         self.builders = infra.get('builders')
         """ Build environments. """
 
-    def _load_nodes(self, nodes: dict) -> int:
+        self.verify = infra.get('verify', False)
+        """ Whether to read back the MTU and addresses of interfaces once configured. """
+
+        self.rollback_on_error = infra.get('rollback_on_error', True)
+        """ Whether the setup script reverts the namespaces, cgroups and physical interfaces it created upon error. """
+        if type(self.rollback_on_error) != bool:
+            raise DuneError('\'rollback_on_error\' should be a boolean.')
+
+        self.namespaces = infra.get('namespaces', 'persistent')
+        """
+        Lifetime of the namespaces created by the setup script.
+        persistent: namespaces are kept once the script exits, until teardown.
+        ephemeral: the script stays in the foreground once set up and reverts everything it created when it exits or is interrupted, e.g., on SIGTERM, but not on SIGKILL. Shell backend only.
+        """
+        if self.namespaces not in ['persistent', 'ephemeral']:
+            raise DuneError('\'namespaces\' should be either \'persistent\' or \'ephemeral\'.')
+
+        self.retries = infra.get('retries', 1)
+        """ Number of attempts for ip commands failing with a transient error (EBUSY, EAGAIN). """
+        if type(self.retries) != int or self.retries < 1:
+            raise DuneError('\'retries\' should be a strictly positive integer.')
+
+    def local(self, override: str = None) -> str:
+        """ Return the phynode of the local machine, i.e., @p override if any, else $DUNE_PHYNODE if set, else the phynode named after the hostname.
+            @param[in]  override    The name of the phynode, if explicitly given.
+            @return     The phynode. DuneError is raised if it is not defined in the infrastructure.
+        """
+        override = override or os.environ.get('DUNE_PHYNODE') or None
+        hostname = socket.gethostname()
+        for phynode in [override] if override is not None else [hostname, hostname.split('.')[0]]:
+            if phynode in self._cores: return phynode
+
+        raise DuneError(f'Phynode <{override or hostname}> not found in infrastructure, known phynodes are {list(self._cores)}.')
+
+    def numa_of(self, phynode: str, core: int) -> int:
+        """ Return the NUMA node id of @p core of @p phynode, or None if @p core is not one of its configured cores. """
+        blocks = self._cores[phynode] if all(type(block) == list for block in self._cores[phynode]) else [self._cores[phynode]]
+        for numa, block in zip(self.numa_ids[phynode], blocks):
+            if core in block: return numa
+        return None
+
+    def _load_nodes(self, nodes: dict):
 
         if len(nodes.keys()) == 0:
-            print('Infrastructure should contain at least one node.')
-            return 1
+            raise DuneError('Infrastructure should contain at least one node.')
         
         for node, cfg in nodes.items():
 
             """ Sanity checks. """
             cores = cfg.get('cores')
             if cores is None:
-                print('\'cores\' not found in node cfg')
-                return 1
+                raise DuneError('\'cores\' not found in node cfg')
 
             if node in self._cores:
-                print(f'node <{node}> redifined.')
-                return 1
+                raise DuneError(f'node <{node}> redifined.')
             
+            sysctls = cfg.get('sysctls')
+            if sysctls is not None:
+                if type(sysctls) != dict or any(type(v) in [dict, list] for v in sysctls.values()):
+                    raise DuneError(f'\'sysctls\' of node <{node}> should map sysctl names to values.')
+                self.sysctls[node] = sysctls
+
+            if (ready := cfg.get('ready')) is not None:
+                if type(ready) != str:
+                    raise DuneError(f'\'ready\' of node <{node}> should be a command.')
+                self.ready[node] = ready
+
+            if (uplink := cfg.get('uplink')) is not None:
+                if type(uplink) != str:
+                    raise DuneError(f'\'uplink\' of node <{node}> should be an interface name.')
+                self.uplinks[node] = uplink
+
+            siblings = cfg.get('siblings')
+            if siblings is not None:
+                self.siblings[node] = {}
+                for group in siblings:
+                    if (group := _expand_cores(group)) is None:
+                        raise DuneError(f'Malformed siblings group in node <{node}>.')
+                    for core in group:
+                        self.siblings[node][core] = [c for c in group if c != core]
+
             """ Collect cores available in specified infrastructure. """
             t = type(cores)
             
             if t == int:
                 self._cores[node] = list(range(1, cores))
                 self._total_cores += cores
+                self.numa_ids[node] = [0]
             elif t == list:
+                """ A block is either a list or range of cores, or {numa: <id>, cores: <cores>} to label it with its NUMA node id. """
+                numa_ids = [block.get('numa') if type(block) == dict else idx for idx, block in enumerate(cores)]
+                if any(type(numa) != int or numa < 0 for numa in numa_ids) \
+                        or any(type(block) == dict and any(key not in ['numa', 'cores'] for key in block) for block in cores):
+                    raise DuneError(f'Labeled block of cores in node <{node}> should be {{numa: <id>, cores: <cores>}}.')
+                if len(set(numa_ids)) != len(numa_ids):
+                    raise DuneError(f'NUMA node ids {numa_ids} of node <{node}> should be unique.')
+                cores = [_expand_cores(block.get('cores') if type(block) == dict else block) for block in cores]
+                if None in cores:
+                    raise DuneError(f'Malformed core range in node <{node}>.')
                 self._cores[node] = cores
+                self.numa_ids[node] = numa_ids
                 self._total_cores += len([c for l in cores for c in l])
             else:
-                print('\'cores\' should be either an integer or a list of list of integers.')
-                return 1
-                
-        return 0
+                raise DuneError('\'cores\' should be either an integer or a list of list of integers or core ranges.')
     
 if __name__ == '__main__':
     infra = Infra('house.yml')
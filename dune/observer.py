@@ -0,0 +1,21 @@
+class Observer:
+    """
+    Observer of the build of a topology, e.g., to report its progress in a UI.
+    Subclass it and override the milestones of interest, then register it with Dune.set_observer().
+    """
+
+    def on_node(self, nid: str, phynode: str):
+        """ Node @p nid, its namespace, processes and files, has been added to the config of @p phynode. """
+        pass
+
+    def on_link(self, head: str, head_iface: str, tail: str, tail_iface: str):
+        """ The link between @p head:@p head_iface and @p tail:@p tail_iface, both of its interfaces, has been added. """
+        pass
+
+    def on_process(self, nid: str, idx: int, cmd: str):
+        """ Pinned process @p idx of node @p nid, launching @p cmd, has been added. """
+        pass
+
+    def on_error(self, error: Exception):
+        """ The build failed with @p error, which is raised once observed. """
+        pass
@@ -1,47 +1,171 @@
 from copy import deepcopy
+from hashlib import sha1
+from ipaddress import ip_address, ip_network
+from re import fullmatch
+from signal import Signals
 from sys import argv
-import yaml
 
 from jinja2 import Environment, meta, Template
 
 import networkx
 
-RESERVED_KEYS = ['pinned', 'sysctls', 'exec', 'templates', 'addrs']
+from dune.config import load
+from dune.errors import DuneError, warn
+
+RESERVED_KEYS = ['pinned', 'sysctls', 'exec', 'templates', 'addrs', 'physical', 'limits', 'label', 'tags', 'routes', 'rules', 'binds', 'profiles', 'external_ns', 'depends_on', 'placement', 'phynode', 'pre_up']
+LIMITS = {'memory_max': 'memory.max', 'cpu_quota': 'cpu.max'}
+ROUTE_KEYS = ['to', 'via', 'dev', 'src', 'table', 'metric', 'mtu']
+RULE_KEYS = ['from', 'to', 'iif', 'oif', 'fwmark', 'table', 'priority']
+IFACE_FLAGS = {
+    'disable_ipv6': 'net.ipv6.conf.{iface}.disable_ipv6',
+    'accept_ra': 'net.ipv6.conf.{iface}.accept_ra',
+    'autoconf': 'net.ipv6.conf.{iface}.autoconf',
+    'rp_filter': 'net.ipv4.conf.{iface}.rp_filter',
+    'proxy_arp': 'net.ipv4.conf.{iface}.proxy_arp',
+}
+
+TUNNELS = {('gre', 4): 'gre', ('gre', 6): 'ip6gre', ('ipip', 4): 'ipip', ('ipip', 6): 'ip6tnl mode ipip6'}
+""" Link type of each tunnel kind, per IP version of its underlay. """
+
+RATE_UNITS = {'bit': 1, 'kbit': 10**3, 'mbit': 10**6, 'gbit': 10**9, 'tbit': 10**12, 'bps': 8, 'kbps': 8 * 10**3, 'mbps': 8 * 10**6, 'gbps': 8 * 10**9, 'tbps': 8 * 10**12}
+""" tc rate units, in bits per second. """
+
+def bandwidth(bw) -> dict:
+    """ Expand the bandwidth @p bw, i.e., a tc rate, e.g., '100mbit', or {rate, burst, limit}, burst and limit being in bytes.
+        The burst defaults to the bytes sent at rate during a 4ms timer tick, at least 1600, and the limit to the burst plus 50ms at rate.
+        @return     {rate, burst, limit}, or None if @p bw is malformed.
+    """
+    bw = dict(rate=bw) if type(bw) == str else bw
+    if type(bw) != dict or any(key not in ['rate', 'burst', 'limit'] for key in bw) \
+            or (m := fullmatch(r'(\d+(?:\.\d+)?)([a-z]+)', str(bw.get('rate')).lower())) is None or m.group(2) not in RATE_UNITS:
+        return None
+
+    rate = float(m.group(1)) * RATE_UNITS[m.group(2)] / 8
+    burst = bw.get('burst', max(1600, int(rate / 250)))
+    return dict(rate=bw['rate'], burst=burst, limit=bw.get('limit', burst + int(rate / 20)))
+
+SCHEDULE_KEYS = ['duration', 'latency', 'loss']
+
+def is_schedule(schedule) -> bool:
+    """ Whether @p schedule is well-formed, i.e., {steps: [{duration: 30s, latency: 200ms, loss: 1%}, ...], repeat: false}. """
+    if type(schedule) != dict or any(key not in ['steps', 'repeat'] for key in schedule) or type(schedule.get('repeat', False)) != bool:
+        return False
+    steps = schedule.get('steps')
+    return type(steps) == list and len(steps) > 0 and all(
+        type(step) == dict and all(key in SCHEDULE_KEYS for key in step)
+        and fullmatch(r'\d+(\.\d+)?[smhd]?', str(step.get('duration'))) is not None for step in steps)
+
+TEMPLATE_SYNTAX = {
+    'variable': ('variable_start_string', 'variable_end_string'),
+    'block': ('block_start_string', 'block_end_string'),
+    'comment': ('comment_start_string', 'comment_end_string'),
+}
+""" Jinja environment options of each configurable delimiter pair of templates. """
+
+def template_syntax(syntax) -> dict:
+    """ Expand the template delimiters @p syntax, e.g., {variable: ['<<', '>>']}, into Jinja environment options.
+        @return     The options, or None if @p syntax is malformed.
+    """
+    if type(syntax) != dict or any(key not in TEMPLATE_SYNTAX for key in syntax) \
+            or any(type(pair) != list or len(pair) != 2 or any(type(d) != str or len(d) == 0 for d in pair) for pair in syntax.values()):
+        return None
+    return {option: delimiter for key, pair in syntax.items() for option, delimiter in zip(TEMPLATE_SYNTAX[key], pair)}
 
 def is_var(token: str) -> str:
     return None if len(token) <=3 or token[0] != '$' or token[1] != '{' or token[-1] != '}' else token[2:-1]
 
+IFNAMSIZ = 15
+""" Maximum length of an interface name, longer names being truncated by the kernel. """
+
+def _override(attributes: dict, override: dict) -> dict:
+    """ Return @p attributes overridden by @p override, dict values such as flags being extended. """
+    ret = dict(attributes)
+    for key, value in override.items():
+        ret[key] = {**ret[key], **value} if isinstance(ret.get(key), dict) and isinstance(value, dict) else value
+    return ret
+
 class Pinned:
     """
     Pinned process representation.
     A pinned process may require multiple cores, e.g., if the process spawns sub-processes.
     """
-    def __init__(self, cmd: str, environ: dict = None, pre_down: list = None, down: str = None):
+    def __init__(self, cmd: str, environ: dict = None, pre_down: list = None, down: str = None, order: int = 0, launcher: str = None, user: str = None, group: str = None, cores: int = None, pidfile: str = None):
         self.cmd = cmd
         """ The shell command to be pinned. """
         self.environ = environ
         """ Optionnal environment variables required by the pinned process. """
         self._cores = {}
         """ IDs of cores required by the process """
+        self.cores = cores
+        """ Optionnal number of cores of the process, overriding the ones referenced by its environment. With 0, the process is not pinned """
         self.pre_down = pre_down
         """ List of instructions to launch before stopping the current process """
         self.down = down
-        """ One line instruction to launch to stop the current process """
+        """
+        One line instruction to launch to stop the current process, or a graceful shutdown, i.e.,
+        {signal: SIGTERM, timeout: 5s, then: SIGKILL}, sending @p signal to the process then @p then, if any, after @p timeout.
+        """
+        self.order = order
+        """ Teardown priority of the current process, lower orders are torn down first """
+        self.launcher = launcher
+        """ Optionnal command wrapping the process, e.g., 'chrt -f 99' or 'numactl --membind 0', which inherits its core affinity """
+        self.user = user
+        """ Optionnal user, name or ID, the process runs as. The launcher, if any, still runs as root. """
+        self.group = group
+        """ Optionnal group, name or ID, the process runs as, the primary group of @p user by default """
+        self.pidfile = pidfile
+        """ Optionnal pidfile written by the process if it daemonizes, e.g., bird, tracked instead of the PID of the launched command """
 
     def __str__(self):
-        return f"cmd <{self.cmd}>\nenviron <{self.environ}>"
+        """ Concise description, e.g., <bird -s r0.sock> on core_0, core_1 as bird, order 1. """
+        ret = f'<{self.cmd}> ' + (f'on {", ".join(self._get_cores())}' if len(self._get_cores()) > 0 else 'unpinned')
+        if self.user is not None: ret += f' as {self.user}' + (f':{self.group}' if self.group is not None else '')
+        if self.order != 0: ret += f', order {self.order}'
+        return ret
 
     def from_dict(cfg: dict):
         cmd = cfg.get('cmd')
         if cmd is None:
-            print("Malformed pinned: 'cmd' not found")
-            return None
-        return Pinned(cmd, environ=cfg.get('environ'), pre_down=cfg.get('pre_down'), down=cfg.get('down'))
+            raise DuneError("Malformed pinned: 'cmd' not found")
+        order = cfg.get('order', 0)
+        if type(order) != int:
+            raise DuneError(f"Malformed pinned <{cmd}>: 'order' should be an integer")
+        down = cfg.get('down')
+        if type(down) == dict:
+            if Pinned._check_signal(down.get('signal')) is None or ('then' in down and Pinned._check_signal(down['then']) is None):
+                raise DuneError(f"Malformed pinned <{cmd}>: unknown signal in 'down', expected e.g. 'SIGTERM'")
+            down = {'timeout': '5s', **down}
+            if fullmatch(r'\d+(\.\d+)?[smhd]?', str(down['timeout'])) is None:
+                raise DuneError(f"Malformed pinned <{cmd}>: 'timeout' should be a duration, e.g., '5s'")
+        user, group = cfg.get('user'), cfg.get('group')
+        if any(type(v) not in [str, int] for v in [user, group] if v is not None):
+            raise DuneError(f"Malformed pinned <{cmd}>: 'user' and 'group' should be names or IDs")
+        if group is not None and user is None:
+            raise DuneError(f"Malformed pinned <{cmd}>: 'group' requires 'user'")
+        pidfile = cfg.get('pidfile')
+        if pidfile is not None and type(pidfile) != str:
+            raise DuneError(f"Malformed pinned <{cmd}>: 'pidfile' should be a path")
+        cores = cfg.get('cores')
+        if cores is not None:
+            if type(cores) != int or cores < 0:
+                raise DuneError(f"Malformed pinned <{cmd}>: 'cores' should be a positive integer")
+            if any(idx >= cores for var, idx in Pinned(cmd, environ=cfg.get('environ'))._get_cores().items() if var != 'core_0'):
+                raise DuneError(f"Malformed pinned <{cmd}>: its environment references more than its {cores} 'cores'")
+        return Pinned(cmd, environ=cfg.get('environ'), pre_down=cfg.get('pre_down'), down=down, order=order, launcher=cfg.get('launcher'),
+                      user=user, group=group, cores=cores, pidfile=pidfile)
+
+    def _check_signal(name: str) -> str:
+        """ Return the signal @p name, e.g., SIGTERM or TERM, or None if it is unknown. """
+        if type(name) != str: return None
+        name = name if name.startswith('SIG') else f'SIG{name}'
+        return name if name in Signals.__members__ else None
         
     def _get_cores(self) -> list:
         """ Lazyly collect cores list required for the current process """
 
-        if len(self._cores) == 0: 
+        if len(self._cores) == 0 and self.cores is not None:
+            self._cores = {f'core_{idx}': idx for idx in range(self.cores)}
+        elif len(self._cores) == 0:
             self._cores['core_0'] = 0
             if self.environ is not None:
                 for var, value in self.environ.items():
@@ -64,10 +188,10 @@ class Pinned:
 class Node:
     """ Represent an emulated node configuration. """
 
-    def __init__(self, pinned:list=None, addrs:dict=None, sysctls:dict=None, execs:list=None, templates:dict=None, env:dict=None):
+    def __init__(self, pinned:list=None, addrs:dict=None, sysctls:dict=None, execs:list=None, templates:dict=None, env:dict=None, physical:dict=None, limits:dict=None, label:str=None, tags:list=None, routes:list=None, rules:list=None, binds:dict=None, external_ns:bool=False, depends_on:list=None, placement:dict=None, phynode:str=None, pre_up:list=None):
         
         # TODO: use classical constructor instead ?
-        self.pinned = None if pinned is None else list(filter(lambda x: x is not None, [Pinned.from_dict(entry) for entry in pinned]))
+        self.pinned = None if pinned is None else [Pinned.from_dict(entry) for entry in pinned]
         """ List of pinned processes, if any, for the current Node. """
         
         self._cores = []
@@ -76,7 +200,7 @@ class Node:
         self.sysctls = sysctls
         """ List of sysctls, if any, to apply upon node initialization. """
         
-        self._addresses = addrs
+        self._addresses = {} if addrs is None else addrs
         """ 
         Dict of addresses in the node. They can be auto-generated or hardcoded.
         The key is the interface and the value a list of addresses regardless of the family.
@@ -84,6 +208,9 @@ class Node:
         
         self.execs = execs
         """ List of one-shot commands, if any, to launch upon node startup. """
+
+        self.pre_up = [] if pre_up is None else pre_up
+        """ List of commands, if any, to launch on the phynode before the node is created, e.g., to load a kernel module. """
         
         self.templates = templates
         """ Dict of templates to generate, if any. """
@@ -91,20 +218,109 @@ class Node:
         self.env = env
         """ Dict with additional user-defied data"""
 
-    def __str__(self):
-        ret = f"pinned:\n"
-        for pinned in self.pinned:
-            ret += f"{pinned}\n\n"
+        self.external_ns = external_ns
+        """ Whether the netns of the node is managed outside Dune, e.g., by a container runtime, and must thus already exist. """
+
+        self.binds = {} if binds is None else binds
+        """
+        Dict of files or directories, if any, copied verbatim into the node.
+        The key is the source, relative to the config, and the value the destination in the node.
+        """
+
+        self.physical = physical
+        """
+        Dict of physical host interfaces, if any, moved into the node.
+        The key is the interface name in the node and the value the name of the interface on the phynode.
+        """
+
+        self.limits = limits
+        """ Dict of cgroup v2 limits, if any, applied to the pinned processes of the node. """
+
+        self.label = label
+        """ Human-readable label, if any, for tooling. It does not affect the setup. """
+
+        self.tags = [] if tags is None else tags
+        """ List of tags, e.g., the role of the node, for tooling. They do not affect the setup. """
+
+        self.routes = [] if routes is None else routes
+        """ List of static routes, e.g., {to: 10.0.0.0/8, via: 10.1.1.1, dev: eth0}, added once interfaces are up. """
+
+        self.rules = [] if rules is None else rules
+        """ List of policy routing rules, e.g., {from: 10.1.0.0/16, table: 100}, added once interfaces are up. """
+
+        self.depends_on = [] if depends_on is None else depends_on
+        """ List of nodes, on the same phynode, whose processes are launched before the ones of the current node. """
+
+        self.placement = placement
+        """
+        Explicit placement, if any, i.e., {phynode: srv1, cores: [4, 5]}, bypassing the allocator.
+        The pinned processes take the cores in order, e.g., core 4 is the core_0 of the first process.
+        """
 
+        self.phynode = phynode if placement is None else placement['phynode']
+        """ Phynode, if any, the node is explicitly assigned to, its cores, if any, being allocated on it. """
+
+    def __str__(self):
+        """ Concise description, e.g., "Core router" [router] 1 process: <bird -s r0.sock> on core_0; eth0 [10.0.0.1/31]. """
+        processes = self.pinned or []
+        parts = [f'"{self.label}"'] if self.label is not None else []
+        if len(self.tags) > 0: parts.append(f'[{", ".join(self.tags)}]')
+        parts.append(f'{len(processes)} process{"es" if len(processes) != 1 else ""}')
+        ret = ' '.join(parts)
+        if len(processes) > 0: ret += ': ' + '; '.join(str(pinned) for pinned in processes)
+        if len(self._addresses) > 0: ret += '; ' + ', '.join(f'{iface} [{", ".join(map(str, addrs or []))}]' for iface, addrs in self._addresses.items())
         return ret
 
     def from_cfg(cfg: dict):
         
         # TODO: check sysctls syntax
 
+        limits = cfg.get('limits')
+        if limits is not None:
+            for limit in limits:
+                if limit not in LIMITS:
+                    raise DuneError(f'Unknown limit <{limit}>, expected one of {list(LIMITS)}.')
+
+        for kind, keys in [('routes', ROUTE_KEYS), ('rules', RULE_KEYS)]:
+            for entry in cfg.get(kind) or []:
+                if type(entry) != dict or any(key not in keys for key in entry):
+                    raise DuneError(f'Malformed {kind} entry <{entry}>, expected keys among {keys}.')
+            if kind == 'routes' and any('to' not in route for route in cfg.get(kind) or []):
+                raise DuneError('Route without \'to\' destination.')
+
+        pre_up = cfg.get('pre_up')
+        if pre_up is not None and (type(pre_up) != list or any(type(cmd) != str for cmd in pre_up)):
+            raise DuneError('\'pre_up\' should be a list of commands.')
+
+        depends_on = cfg.get('depends_on')
+        if depends_on is not None and (type(depends_on) != list or any(type(dep) != str for dep in depends_on)):
+            raise DuneError('\'depends_on\' should be a list of node names.')
+
+        placement = cfg.get('placement')
+        if placement is not None:
+            if type(placement) != dict or type(placement.get('phynode')) != str \
+                    or type(placement.get('cores')) != list or any(type(core) != int for core in placement['cores']):
+                raise DuneError('\'placement\' should be {phynode: <phynode>, cores: [<core>, ...]}.')
+
+        phynode = cfg.get('phynode')
+        if phynode is not None:
+            if type(phynode) != str:
+                raise DuneError('\'phynode\' should be the name of a phynode.')
+            if placement is not None and placement['phynode'] != phynode:
+                raise DuneError(f'\'phynode\' <{phynode}> conflicts with the phynode of \'placement\' <{placement["phynode"]}>.')
+
+        """ A template maps to its destination, or to {dst, syntax} to override the delimiters of the topology. """
         templates = cfg.get('templates')
         if templates is not None:
-            templates = {k: {'dst': v, 'content': None} for k, v in templates.items()}
+            templates = {k: dict(v) if type(v) == dict else {'dst': v} for k, v in templates.items()}
+            for template, data in templates.items():
+                if type(data.get('dst')) != str or any(key not in ['dst', 'syntax'] for key in data):
+                    raise DuneError(f'Template <{template}> should map to a destination or to {{dst, syntax}}.')
+                if 'syntax' in data:
+                    if (syntax := template_syntax(data['syntax'])) is None:
+                        raise DuneError(f'\'syntax\' of template <{template}> should map variable, block or comment to [start, end] delimiters.')
+                    data['syntax'] = syntax
+                data['content'] = None
         env = {k: v for k, v in cfg.items() if k not in RESERVED_KEYS}
 
         return Node(
@@ -113,15 +329,47 @@ class Node:
             execs = cfg.get('exec'),
             addrs = cfg.get('addrs'),
             templates = templates,
-            env = env
+            env = env,
+            physical = cfg.get('physical'),
+            limits = limits,
+            label = cfg.get('label'),
+            tags = cfg.get('tags'),
+            routes = cfg.get('routes'),
+            rules = cfg.get('rules'),
+            binds = cfg.get('binds'),
+            external_ns = cfg.get('external_ns', False),
+            depends_on = depends_on,
+            placement = placement,
+            phynode = phynode,
+            pre_up = pre_up
         )
 
     def _get_cores(self) -> list:
         if len(self._cores) == 0:
-            for pinned in self.pinned:
+            for pinned in self.pinned or []:
                 self._cores.append(pinned._get_cores())
         return self._cores
 
+    def _check_cores(self, nid: str):
+        """ Report the core layout of node @p nid that is likely unintended, before allocation.
+            @post       Gaps in the core ids of a process are reported, e.g., a forgotten core_1 next to core_2.
+            @post       Core ids referenced by several processes are reported, as each process gets its own cores.
+                        Processes with an explicit number of 'cores' are not concerned.
+        """
+        for idx, cores in enumerate(self._get_cores()):
+            ids = sorted(int(core[5:]) for core in cores)
+            if len(ids) > 0 and len(gaps := sorted(set(range(ids[-1] + 1)) - set(ids))) > 0:
+                warn(f'Process {idx} of node <{nid}> references core ids {ids} but not {gaps}.')
+
+        shared = {}
+        for idx, (pinned, cores) in enumerate(zip(self.pinned, self._get_cores())):
+            if pinned.cores is not None: continue
+            for core in cores:
+                if core != 'core_0': shared.setdefault(core, []).append(idx)
+        for core, processes in shared.items():
+            if len(processes) > 1:
+                warn(f'Processes {processes} of node <{nid}> all reference {core}, each of them gets its own core.')
+
     def _get_n_cores(self) -> int:
         counter = 0
         for cores in self._get_cores():
@@ -130,32 +378,62 @@ class Node:
 
 class Topo(networkx.MultiDiGraph):
 
-    def __init__(self, path: str):
+    def __init__(self, path: str, profiles: list = None):
         super().__init__()
         self._total_cores = 0
+        self.profiles = set(profiles or [])
+        """ Active profiles. Nodes and links declaring 'profiles' are only included if one of them is active. """
         self._load_topo(path)
 
+    def _enabled(self, profiles: list) -> bool:
+        """ Whether an element declaring @p profiles, if any, is included. """
+        return profiles is None or len(self.profiles & set(profiles)) > 0
+
     def _load_topo(self, path: str):
 
         # TODO: check that path exists
 
-        with open(path) as fd:
-            cfg = yaml.load(fd, yaml.Loader)
+        cfg = load(path)
 
         """ Check that mandatory sections are present. """
         try:
             topo = cfg['topology']
         except KeyError:
-            print('No topology found in the configuration')
-            exit(1)
+            raise DuneError('No topology found in the configuration')
 
-        if 'links' not in topo:
-            print("No links found")
-            exit(1)
-    
         if 'nodes' not in topo:
-            print("No nodes found")
-            exit(1)
+            raise DuneError("No nodes found")
+
+        self.generate_hosts = topo.get('generate_hosts', False)
+        """ Whether each node gets a hosts file mapping the loopback addresses of every node to its name. """
+
+        self.template_syntax = template_syntax(topo.get('template_syntax', {}))
+        """ Jinja environment options setting the delimiters of templates, e.g., {variable: ['<<', '>>']} leaves '{{ }}' as is. """
+        if self.template_syntax is None:
+            raise DuneError('\'template_syntax\' should map variable, block or comment to [start, end] delimiters.')
+
+        self.management = topo.get('management')
+        """
+        Management network, if any, i.e., {subnet, iface, bridge, vlan}, giving each node an interface on a bridge of its phynode.
+        The bridges of the phynodes are joined by a VLAN over their uplinks, 4094 by default, so that the network spans every phynode.
+        """
+        if self.management is not None:
+            if type(self.management) != dict or any(key not in ['subnet', 'iface', 'bridge', 'vlan'] for key in self.management):
+                raise DuneError('\'management\' should be {subnet, iface, bridge, vlan}.')
+            try:
+                ip_network(self.management.get('subnet'))
+            except ValueError:
+                raise DuneError('\'subnet\' of \'management\' should be a network, e.g., 172.31.0.0/16.')
+            self.management = {'iface': 'mgmt0', 'bridge': 'dune-mgmt', 'vlan': 4094, **self.management}
+            if type(self.management['vlan']) != int or not 2 <= self.management['vlan'] <= 4094:
+                raise DuneError('\'vlan\' of \'management\' should be a VLAN ID between 2 and 4094.')
+
+        self.ifnames = topo.get('ifnames', 'strict')
+        """ Handling of interface names exceeding IFNAMSIZ, i.e., 'strict' rejects them, 'hash' abbreviates them. """
+        if self.ifnames not in ['strict', 'hash']:
+            raise DuneError('\'ifnames\' should be either \'strict\' or \'hash\'.')
+        self.aliases = {}
+        """ Full name of each abbreviated interface, i.e., {(nid, iface): name}. """
 
         """ Get defaults, if any. """
         links_defaults = None
@@ -164,40 +442,140 @@ class Topo(networkx.MultiDiGraph):
             links_defaults = topo['defaults'].get('links')
             nodes_defaults = topo['defaults'].get('nodes')
         
-        """ Parse mandatory sections. """
-        if self._parse_links(topo['links'], links_defaults) != 0: exit(1)
-        if self._parse_nodes(topo['nodes'], nodes_defaults) != 0: exit(1)
+        """ Drop the nodes of inactive profiles, and their links. """
+        default_profiles = (nodes_defaults or {}).get('profiles')
+        nodes = {nid: cfg for nid, cfg in topo['nodes'].items() if self._enabled((cfg or {}).get('profiles', default_profiles))}
+        links = [link for link in topo.get('links', []) if self._enabled(link.pop('profiles', None))]
+        """ Only links to defined nodes are dropped, an undefined node being an error, e.g., a typo. """
+        for link in links:
+            for endpoint in link.get('endpoints', []):
+                if (nid := str(endpoint).split(':')[0]) not in topo['nodes']:
+                    raise DuneError(f'Link endpoint <{endpoint}> references unknown node <{nid}>.')
+        links = [link for link in links if all(endpoint.split(':')[0] in nodes for endpoint in link.get('endpoints', []))]
+
+        """ Parse mandatory sections. Links are optional, e.g., for loopback-only nodes. """
+        self._parse_links(links, links_defaults)
+        self._parse_nodes(nodes, nodes_defaults)
+
+        if self.management is not None:
+            for nid, _, (iface, _) in self.edges(keys=True):
+                if iface == self.management['iface']:
+                    raise DuneError(f'Interface <{nid}:{iface}> conflicts with the management interface.')
+
+        """ Dependencies on nodes of inactive profiles are dropped. """
+        for nid, node in self.nodes(data=True):
+            for dep in node['cfg'].depends_on:
+                if dep not in topo['nodes']:
+                    raise DuneError(f'Node <{nid}> depends on unknown node <{dep}>.')
+            node['cfg'].depends_on = [dep for dep in node['cfg'].depends_on if dep in nodes]
+
+    def startup_order(self) -> list:
+        """ Return the nodes ordered such that each node comes after the nodes it depends on, the config order being kept otherwise.
+            @return     The ordered node ids. DuneError is raised on dependency cycles.
+        """
+        deps = networkx.DiGraph()
+        deps.add_nodes_from(self.nodes())
+        deps.add_edges_from((dep, nid) for nid, node in self.nodes(data=True) for dep in node['cfg'].depends_on)
+
+        order = list(self.nodes())
+        try:
+            return list(networkx.lexicographical_topological_sort(deps, key=order.index))
+        except networkx.NetworkXUnfeasible:
+            raise DuneError(f'Dependency cycle between nodes: {networkx.find_cycle(deps)}.')
     
-    def _parse_links(self, links: list, defaults: dict = None) -> int:
+    def _ifname(self, nid: str, iface: str) -> str:
+        """ Return the kernel name of interface @p iface of node @p nid, at most IFNAMSIZ characters long.
+            A longer name is abbreviated with a hash of it if 'ifnames' is 'hash', its full name being recorded in aliases.
+            DuneError is raised otherwise.
+        """
+        if len(iface) <= IFNAMSIZ: return iface
+        if self.ifnames != 'hash':
+            raise DuneError(f'Interface name <{nid}:{iface}> exceeds {IFNAMSIZ} characters, shorten it or set \'ifnames: hash\'.')
+
+        ifname = f'{iface[:IFNAMSIZ - 6]}-{sha1(iface.encode()).hexdigest()[:5]}'
+        if self.aliases.setdefault((nid, ifname), iface) != iface:
+            raise DuneError(f'Interface names <{nid}:{iface}> and <{nid}:{self.aliases[nid, ifname]}> are both abbreviated as <{ifname}>.')
+        return ifname
+
+    def _parse_links(self, links: list, defaults: dict = None):
 
         def parse_endpoint(v: str) -> tuple[str, str]:
             return v.split(':')
 
+        used = set()
         for link in links:
             try:
                 endpoints = link['endpoints']
                 if len(endpoints) != 2:
-                    print('Unexpected number of entries in endpoint')
-                    return 1
+                    raise DuneError('Unexpected number of entries in endpoint')
                 head, tail = endpoints
                 head_node, head_iface = parse_endpoint(head)
                 tail_node, tail_iface = parse_endpoint(tail)
                 del link['endpoints']
 
-                for def_key, def_val in defaults.items():
-                    if def_key not in link:
-                        link[def_key] = def_val
-
-                self.add_edge(head_node, tail_node, key=(head_iface, tail_iface), **link)
-                self.add_edge(tail_node, head_node, key=(tail_iface, head_iface), **link)
+                """ An interface belongs to a single link, otherwise link IDs are ambiguous. """
+                for endpoint in [(head_node, head_iface), (tail_node, tail_iface)]:
+                    if endpoint in used:
+                        raise DuneError(f'Interface <{endpoint[0]}:{endpoint[1]}> used by multiple links.')
+                    used.add(endpoint)
+
+                if defaults is not None:
+                    for def_key, def_val in defaults.items():
+                        if def_key not in link:
+                            link[def_key] = def_val
+
+                """ Per-endpoint attributes, if any, override the link attributes on that endpoint only. """
+                overrides = link.pop('overrides', None) or {}
+                for endpoint, attributes in overrides.items():
+                    if endpoint not in [head, tail]:
+                        raise DuneError(f'Override <{endpoint}> is not an endpoint of link {endpoints}.')
+                    if 'mtu' in attributes and not link.get('asymmetric_mtu', False):
+                        raise DuneError(f'MTU of link {endpoints} cannot be overridden per endpoint unless \'asymmetric_mtu\' is set.')
+                    if 'kind' in attributes:
+                        raise DuneError(f'Kind of link {endpoints} cannot be overridden per endpoint.')
+
+                """ Tunnels are set up over the 'local' underlay address of each endpoint, of the same IP version. """
+                if (kind := link.get('kind', 'veth')) not in ['veth', *[k for k, _ in TUNNELS]]:
+                    raise DuneError(f'Unknown kind <{kind}> of link {endpoints}, expected veth, gre or ipip.')
+                if kind != 'veth':
+                    try:
+                        versions = set(ip_address(_override(link, overrides.get(endpoint, {})).get('local')).version for endpoint in [head, tail])
+                    except ValueError:
+                        raise DuneError(f'Tunnel {endpoints} requires the \'local\' underlay address of each endpoint.')
+                    if len(versions) != 1:
+                        raise DuneError(f'Underlay addresses of tunnel {endpoints} should be of the same IP version.')
+
+                for attributes in [link, *overrides.values()]:
+                    for flag in attributes.get('flags') or {}:
+                        if flag not in IFACE_FLAGS:
+                            raise DuneError(f'Unknown interface flag <{flag}> in link {endpoints}, expected one of {list(IFACE_FLAGS)}.')
+                    if 'txqueuelen' in attributes and (type(attributes['txqueuelen']) != int or attributes['txqueuelen'] < 0):
+                        raise DuneError(f'\'txqueuelen\' of link {endpoints} should be a positive integer.')
+                    if any(type(attributes[mode]) != bool for mode in ['promisc', 'allmulticast'] if mode in attributes):
+                        raise DuneError(f'\'promisc\' and \'allmulticast\' of link {endpoints} should be booleans.')
+                    if any(type(value) != bool for value in (attributes.get('offloads') or {}).values()):
+                        raise DuneError(f'\'offloads\' of link {endpoints} should map features, e.g., tso, to on or off.')
+                    if 'bw' in attributes and bandwidth(attributes['bw']) is None:
+                        raise DuneError(f'\'bw\' of link {endpoints} should be a rate, e.g., \'100mbit\', or {{rate, burst, limit}}.')
+                    if 'qdisc' in attributes and type(attributes['qdisc']) != str:
+                        raise DuneError(f'\'qdisc\' of link {endpoints} should be a qdisc and its parameters, e.g., \'fq_codel limit 1000\'.')
+                    if 'capture' in attributes and type(attributes['capture']) not in [bool, str]:
+                        raise DuneError(f'\'capture\' of link {endpoints} should be a boolean or a filter expression.')
+                    if 'xdp' in attributes and type(attributes['xdp']) != str:
+                        raise DuneError(f'\'xdp\' of link {endpoints} should be the path of a compiled XDP program.')
+                    if 'metric' in attributes and (type(attributes['metric']) != int or attributes['metric'] < 0):
+                        raise DuneError(f'\'metric\' of link {endpoints} should be a positive integer.')
+                    if 'schedule' in attributes and not is_schedule(attributes['schedule']):
+                        raise DuneError(f'\'schedule\' of link {endpoints} should be {{steps: [{{duration, latency, loss}}, ...], repeat}}.')
+
+                head_iface, tail_iface = self._ifname(head_node, head_iface), self._ifname(tail_node, tail_iface)
+                self.add_edge(head_node, tail_node, key=(head_iface, tail_iface), **_override(link, overrides.get(head, {})))
+                self.add_edge(tail_node, head_node, key=(tail_iface, head_iface), **_override(link, overrides.get(tail, {})))
           
             except KeyError:
-                print('No endpoint defined in link')
-                return 1
-
-        return 0
+                raise DuneError('No endpoint defined in link')
         
-    def _parse_nodes(self, nodes: dict, defaults: dict = None) -> int:
+    def _parse_nodes(self, nodes: dict, defaults: dict = None):
         
         for node, config in nodes.items():
             """ For each node, expand config from defaults, if any. """
@@ -218,11 +596,20 @@ class Topo(networkx.MultiDiGraph):
         
 
             n = Node.from_cfg(node_cfg)
+
+            """ Interfaces are referred to by their kernel name. """
+            n._addresses = {self._ifname(node, iface): addrs for iface, addrs in n._addresses.items()}
+            if n.physical is not None:
+                n.physical = {self._ifname(node, iface): host_iface for iface, host_iface in n.physical.items()}
+            for route in n.routes:
+                if 'dev' in route: route['dev'] = self._ifname(node, route['dev'])
+            for rule in n.rules:
+                for key in ['iif', 'oif']:
+                    if key in rule: rule[key] = self._ifname(node, str(rule[key]))
+            if n.pinned is not None: n._check_cores(node)
             self._total_cores += n._get_n_cores()
             self.add_node(node, cfg=n)
 
-        return 0
-
 if __name__ == "__main__":
     topo = Topo("house.yml")
     print(topo._total_cores)
@@ -0,0 +1,21 @@
+import pytest
+import yaml
+
+from dune import Dune
+
+@pytest.fixture
+def make_dune(tmp_path):
+    """ Return a factory of Dune instances for a topology, written with its templates, if any, in a scratch directory.
+        The infrastructure defaults to a single phynode, srv1.
+    """
+
+    def make(topology: dict, infrastructure: dict = None, templates: dict = None) -> Dune:
+        for name, content in (templates or {}).items():
+            (tmp_path / 'templates').mkdir(exist_ok=True)
+            (tmp_path / 'templates' / name).write_text(content)
+        infrastructure = infrastructure or {'nodes': {'srv1': {'cores': [[1, 2, 3, 4, 5, 6, 7, 8]]}}}
+        with open(tmp_path / 'topo.yml', 'w') as fd:
+            yaml.dump(dict(infrastructure=infrastructure, topology=topology), fd)
+        return Dune(str(tmp_path), 'topo.yml')
+
+    return make
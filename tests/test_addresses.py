@@ -0,0 +1,50 @@
+import pytest
+
+from dune import ConfigSection, _address
+from dune.errors import DuneError
+
+ADDRS = ['10.0.0.1/31', '2001:db8::1/64', '192.0.2.1/24', 'fe80::1/64']
+
+def test_mixed_addresses_assigned_and_rendered(make_dune):
+    dune = make_dune({
+        'links': [{'endpoints': ['r0:eth0', 'r1:eth0']}],
+        'nodes': {
+            'r0': {'addrs': {'eth0': ADDRS}, 'templates': {'addrs.j2': '/etc/r0.addrs'}},
+            'r1': {},
+        },
+    }, templates={'addrs.j2': '{% for addr in ifaces.eth0.addrs %}{{ addr }}\n{% endfor %}'})
+    dune.build()
+
+    links = dune._configs['srv1'][ConfigSection.Links]
+    for addr in ADDRS:
+        assert f'ip -n r0 a add {addr} dev eth0' in links
+
+    assert dune.topo.nodes['r0']['cfg'].templates['addrs.j2']['content'].split() == ADDRS
+
+@pytest.mark.parametrize('addr, expected', [
+    ('10.0.0.1/32', '10.0.0.1/32'),
+    ('10.0.0.0/31', '10.0.0.0/31'),
+    ('10.0.0.1/31', '10.0.0.1/31'),
+    ('2001:db8::1/128', '2001:db8::1/128'),
+    ('10.0.0.1', '10.0.0.1/32'),
+    ('2001:db8::1', '2001:db8::1/128'),
+])
+def test_address_prefix(addr, expected):
+    assert _address('r0', 'eth0', addr) == expected
+
+@pytest.mark.parametrize('addr', ['10.0.0.256/32', '10.0.0.1/33', 'r0'])
+def test_malformed_address(addr):
+    with pytest.raises(DuneError, match='Malformed address'):
+        _address('r0', 'eth0', addr)
+
+def test_lo_addresses_expanded_once(make_dune, tmp_path):
+    (tmp_path / 'plugins').mkdir()
+    (tmp_path / 'plugins' / 'lo_counter.py').write_text('calls = []\n\ndef lo(node):\n    calls.append(node)\n    return f"10.255.0.{len(calls)}/32"\n')
+    dune = make_dune({
+        'nodes': {'r0': {'addrs': {'lo': ['%fun lo_counter.lo("{{ node }}")']}}},
+    })
+    dune.build()
+    dune.stats()
+
+    assert dune._plugins['lo_counter'].calls == ['r0']
+    assert 'ip -n r0 a add 10.255.0.1/32 dev lo' in dune._configs['srv1'][ConfigSection.Nodes]
@@ -0,0 +1,9 @@
+import pytest
+
+from dune.config import load
+from dune.errors import DuneError
+
+def test_remote_config_requires_opt_in(monkeypatch):
+    monkeypatch.delenv('DUNE_ALLOW_REMOTE', raising=False)
+    with pytest.raises(DuneError, match='--allow-remote'):
+        load('https://example.invalid/topo.yml')
@@ -0,0 +1,21 @@
+import json
+
+from dune import ConfigSection
+
+def test_json_round_trip(make_dune, tmp_path):
+    dune = make_dune({
+        'links': [{'endpoints': ['r0:eth0', 'r1:eth0'], 'latency': '5ms'}],
+        'nodes': {
+            'r0': {'addrs': {'eth0': ['10.0.0.0/31']}, 'pinned': [{'cmd': 'sleep 10', 'down': 'pkill sleep'}]},
+            'r1': {'addrs': {'eth0': ['10.0.0.1/31']}},
+        },
+    })
+    dune.build()
+    dune.dump(format='json')
+
+    with open(tmp_path / '.dune' / 'srv1') as fd:
+        dumped = json.load(fd)
+
+    assert list(dumped) == [str(section) for section in ConfigSection]
+    for section in ConfigSection:
+        assert dumped[section] == dune._configs['srv1'].get(section, [])
@@ -0,0 +1,93 @@
+import os
+import shutil
+import subprocess
+
+import pytest
+
+from dune import ConfigSection
+from dune.errors import DuneError
+
+def test_self_loop_and_parallel_links(make_dune):
+    dune = make_dune({
+        'links': [
+            {'endpoints': ['r0:eth0', 'r0:eth1']},
+            {'endpoints': ['r0:eth2', 'r1:eth0']},
+            {'endpoints': ['r0:eth3', 'r1:eth1']},
+        ],
+        'nodes': {'r0': {}, 'r1': {}},
+    })
+    dune.build()
+
+    links = dune.links()
+    assert sorted(link['id'] for link in links) == ['r0:eth0-r0:eth1', 'r0:eth2-r1:eth0', 'r0:eth3-r1:eth1']
+    endpoints = [(endpoint['node'], endpoint['iface']) for link in links for endpoint in link['endpoints']]
+    assert len(endpoints) == len(set(endpoints)) == 6
+
+    created = [cmd for cmd in dune._configs['srv1'][ConfigSection.Links] if 'type veth' in cmd]
+    assert created == [
+        'ip l add dev eth0 netns r0 type veth peer name eth1 netns r0',
+        'ip l add dev eth2 netns r0 type veth peer name eth0 netns r1',
+        'ip l add dev eth3 netns r0 type veth peer name eth1 netns r1',
+    ]
+
+def test_asymmetric_mtu(make_dune):
+    dune = make_dune({
+        'links': [{
+            'endpoints': ['r0:eth0', 'r1:eth0'],
+            'mtu': 1500,
+            'asymmetric_mtu': True,
+            'overrides': {'r1:eth0': {'mtu': 9000}},
+        }],
+        'nodes': {'r0': {}, 'r1': {}},
+    })
+    dune.build()
+
+    created = dune._configs['srv1'][ConfigSection.Links]
+    assert 'ip -n r0 l set dev eth0 mtu 1500' in created
+    assert 'ip -n r1 l set dev eth0 mtu 9000' in created
+    assert 'ip -n r1 l set dev eth0 mtu 1500' not in created
+
+def test_symmetric_mtu_rejects_override(make_dune):
+    with pytest.raises(DuneError, match='asymmetric_mtu'):
+        make_dune({
+            'links': [{
+                'endpoints': ['r0:eth0', 'r1:eth0'],
+                'mtu': 1500,
+                'overrides': {'r1:eth0': {'mtu': 9000}},
+            }],
+            'nodes': {'r0': {}, 'r1': {}},
+        })
+
+FAKE_IP = '''#!/bin/sh
+# Interfaces are files named <netns>/<iface> in $IFACES, adding an existing one fails.
+if [ "$1" = -n ]; then ns=$2; shift 2; fi
+case "$1 $2" in
+    "l show") [ -e "$IFACES/$ns/$4" ];;
+    "l del") [ -e "$IFACES/$ns/$4" ] && rm "$IFACES/$ns/$4";;
+    "l add") [ ! -e "$IFACES/$6/$4" ] && [ ! -e "$IFACES/${13}/${11}" ] || exit 2
+             mkdir -p "$IFACES/$6" "$IFACES/${13}"; touch "$IFACES/$6/$4" "$IFACES/${13}/${11}";;
+esac
+'''
+
+def test_ensure_recreates_stale_veth(make_dune, tmp_path):
+    dune = make_dune({'links': [{'endpoints': ['r0:eth0', 'r1:eth0']}], 'nodes': {'r0': {}, 'r1': {}}})
+    dune.build(ensure=True)
+
+    veth = [cmd for cmd in dune._configs['srv1'][ConfigSection.Links] if 'type veth' in cmd]
+    assert veth == ['{ ip -n r0 l show dev eth0 && ip -n r1 l show dev eth0; } > /dev/null 2>&1 || '
+                    '{ ip -n r0 l del dev eth0 2> /dev/null || true; ip -n r1 l del dev eth0 2> /dev/null || true; '
+                    'ip l add dev eth0 netns r0 type veth peer name eth0 netns r1; }']
+
+    """ Run it against a fake ip, from nothing, from a stale end without its peer, and from the complete pair. """
+    (bin := tmp_path / 'bin').mkdir()
+    (bin / 'ip').write_text(FAKE_IP)
+    (bin / 'ip').chmod(0o755)
+    ifaces = tmp_path / 'ifaces'
+    env = {**os.environ, 'PATH': f'{bin}:{os.environ["PATH"]}', 'IFACES': str(ifaces)}
+    for stale in [[], ['r0/eth0'], ['r0/eth0', 'r1/eth0']]:
+        shutil.rmtree(ifaces, ignore_errors=True)
+        for iface in stale:
+            (ifaces / iface).parent.mkdir(parents=True, exist_ok=True)
+            (ifaces / iface).touch()
+        subprocess.run(['sh', '-c', veth[0]], env=env, check=True)
+        assert sorted(str(path.relative_to(ifaces)) for path in ifaces.glob('*/*')) == ['r0/eth0', 'r1/eth0']
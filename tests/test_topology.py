@@ -0,0 +1,44 @@
+from dune import ConfigSection
+
+def test_colon_attribute_is_not_an_endpoint(make_dune):
+    dune = make_dune({
+        'links': [{
+            'endpoints': ['r0:eth0', 'r1:eth0'],
+            'latency': '5ms',
+            'r0:eth0': {'latency': '50ms'},
+            'vendor:opt': 'x',
+            'overrides': {'r1:eth0': {'latency': '10ms'}},
+        }],
+        'nodes': {'r0': {}, 'r1': {}},
+    })
+
+    head, tail = dune.topo.edges['r0', 'r1', ('eth0', 'eth0')], dune.topo.edges['r1', 'r0', ('eth0', 'eth0')]
+    assert head['latency'] == '5ms' and tail['latency'] == '10ms'
+    assert head['vendor:opt'] == tail['vendor:opt'] == 'x'
+    assert head['r0:eth0'] == {'latency': '50ms'}
+
+    dune.build()
+    links = dune._configs['srv1'][ConfigSection.Links]
+    assert any(cmd.startswith('ip netns exec r0 ') and 'delay 5ms' in cmd for cmd in links)
+    assert not any('delay 50ms' in cmd for cmd in links)
+
+def test_hashed_ifnames_in_routes_and_rules(make_dune):
+    dune = make_dune({
+        'ifnames': 'hash',
+        'links': [{'endpoints': ['r0:to-the-core-router', 'r1:eth0']}],
+        'nodes': {
+            'r0': {
+                'routes': [{'to': '10.1.0.0/16', 'dev': 'to-the-core-router'}],
+                'rules': [{'iif': 'to-the-core-router', 'table': 10}, {'oif': 'to-the-core-router', 'table': 20}],
+            },
+            'r1': {},
+        },
+    })
+    ifname = next(iface for _, _, (iface, _) in dune.topo.edges('r0', keys=True))
+    assert len(ifname) <= 15 and ifname != 'to-the-core-router'
+
+    dune.build()
+    links = dune._configs['srv1'][ConfigSection.Links]
+    assert f'ip -n r0 r add 10.1.0.0/16 dev {ifname}' in links
+    assert f'ip -n r0 rule add iif {ifname} table 10' in links
+    assert f'ip -n r0 rule add oif {ifname} table 20' in links